@@ -1,16 +1,24 @@
-use cf_ws_v1::WebSocket;
+use cf_ws_v1::{Error, Feed, WebSocket};
 
 const API_PATH: &str = "wss://www.cryptofacilities.com/ws/v1";
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() {
+async fn main() -> cf_ws_v1::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let mut ws = WebSocket::new(API_PATH, None, None).await;
+    let mut ws = WebSocket::new(API_PATH, None, None).await?;
 
-    ws.subscribe("ticker", Some(&["PI_XBTUSD"])).await;
+    ws.subscribe(Feed::Ticker, Some(&["PI_XBTUSD".into()]))
+        .await?
+        .await?;
 
-    while let Some(msg) = ws.next_msg().await {
-        log::info!("{msg:?}");
+    loop {
+        match ws.next_msg().await {
+            Ok(msg) => log::info!("{msg:?}"),
+            Err(Error::ChannelClosed) => break,
+            Err(err) => log::error!("{err}"),
+        }
     }
+
+    Ok(())
 }
@@ -0,0 +1,56 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    sync::oneshot,
+    time::{self, Instant, Sleep},
+};
+
+use crate::{Error, Result};
+
+/// Resolves once the server has acknowledged a subscription request.
+///
+/// Completes with [`Error::Subscribe`] if the server rejects the request, or with
+/// [`Error::Timeout`] if no answer arrives within the
+/// [subscribe timeout](crate::WebSocketBuilder::subscribe_timeout). Dropping it does not cancel
+/// the subscription.
+#[derive(Debug)]
+pub struct Ack {
+    rx: oneshot::Receiver<Result<()>>,
+    timeout: Option<Pin<Box<Sleep>>>,
+}
+
+impl Ack {
+    // the connection task gives up on the request at the same `deadline`
+    pub(crate) fn new(deadline: Option<Instant>) -> (oneshot::Sender<Result<()>>, Self) {
+        let (tx, rx) = oneshot::channel();
+
+        let ack = Self {
+            rx,
+            timeout: deadline.map(|deadline| Box::pin(time::sleep_until(deadline))),
+        };
+
+        (tx, ack)
+    }
+}
+
+impl Future for Ack {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(res) = Pin::new(&mut self.rx).poll(cx) {
+            return Poll::Ready(res.unwrap_or(Err(Error::ChannelClosed)));
+        }
+
+        match &mut self.timeout {
+            Some(timeout) => timeout
+                .as_mut()
+                .poll(cx)
+                .map(|()| Err(Error::Timeout("subscription acknowledgement"))),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{models, BookSnapshot, BookValue, Price, Product, Qty, Side};
+
+// price usable as a map key; prices are never NaN
+#[derive(Debug, Clone, Copy)]
+struct Level(Price);
+
+impl PartialEq for Level {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Level {}
+
+impl PartialOrd for Level {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Level {
+    #[cfg(not(feature = "decimal"))]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+
+    #[cfg(feature = "decimal")]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// Outcome of applying a delta to an [`OrderBook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    /// The delta followed the last one and was applied.
+    Applied,
+
+    /// The delta repeated the last sequence number and was skipped.
+    Duplicate,
+
+    /// The delta was older than the last one and was skipped.
+    Stale,
+
+    /// Deltas were missed; this one was not applied and the book can no longer be trusted.
+    Gap { expected: u64, received: u64 },
+
+    /// The delta was for another product or had no known side.
+    Ignored,
+}
+
+/// Counters of out-of-sequence book deltas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    pub gaps: u64,
+    pub duplicates: u64,
+    pub stale: u64,
+
+    /// Fresh snapshots requested after a gap.
+    pub resyncs: u64,
+}
+
+impl SequenceStats {
+    fn add(&mut self, other: &SequenceStats) {
+        self.gaps += other.gaps;
+        self.duplicates += other.duplicates;
+        self.stale += other.stale;
+        self.resyncs += other.resyncs;
+    }
+}
+
+/// A book that missed deltas and is being rebuilt from a fresh snapshot.
+#[derive(Debug, Clone)]
+pub struct BookInvalidated {
+    pub product: Product,
+
+    /// Sequence number that should have come next.
+    pub expected: u64,
+
+    /// Sequence number that was received instead.
+    pub received: u64,
+}
+
+/// Local copy of the order book of one product, built from a `book_snapshot` and kept up to
+/// date with `book` deltas.
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    product: Product,
+    seq: u64,
+    bids: BTreeMap<Level, Qty>,
+    asks: BTreeMap<Level, Qty>,
+}
+
+impl OrderBook {
+    /// Builds the book from a snapshot.
+    pub fn new(snapshot: &BookSnapshot) -> Self {
+        let levels = |values: &[BookValue]| {
+            values
+                .iter()
+                .filter(|value| value.qty > Qty::default())
+                .map(|value| (Level(value.price), value.qty))
+                .collect()
+        };
+
+        Self {
+            product: snapshot.product_id.clone(),
+            seq: snapshot.seq,
+            bids: levels(&snapshot.bids),
+            asks: levels(&snapshot.asks),
+        }
+    }
+
+    /// Applies a delta; a quantity of zero removes the price level.
+    ///
+    /// Deltas must follow each other in `seq` order; anything else leaves the book unchanged.
+    /// After a [`Sequence::Gap`] the book is missing updates and should be rebuilt from a new
+    /// snapshot.
+    pub fn apply(&mut self, delta: &BookValue) -> Sequence {
+        if delta
+            .product_id
+            .as_ref()
+            .is_some_and(|product| *product != self.product)
+        {
+            return Sequence::Ignored;
+        }
+
+        let levels = match delta.side {
+            Some(Side::Buy) => &mut self.bids,
+            Some(Side::Sell) => &mut self.asks,
+            _ => return Sequence::Ignored,
+        };
+
+        if let Some(seq) = delta.seq {
+            let expected = self.seq + 1;
+
+            match seq.cmp(&expected) {
+                Ordering::Less if seq == self.seq => return Sequence::Duplicate,
+                Ordering::Less => return Sequence::Stale,
+                Ordering::Greater => {
+                    return Sequence::Gap {
+                        expected,
+                        received: seq,
+                    }
+                }
+                Ordering::Equal => self.seq = seq,
+            }
+        }
+
+        if delta.qty > Qty::default() {
+            levels.insert(Level(delta.price), delta.qty);
+        } else {
+            levels.remove(&Level(delta.price));
+        }
+
+        Sequence::Applied
+    }
+
+    /// Product the book is for.
+    pub fn product(&self) -> &Product {
+        &self.product
+    }
+
+    /// Sequence number of the last applied snapshot or delta.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Highest bid, as `(price, qty)`.
+    pub fn best_bid(&self) -> Option<(Price, Qty)> {
+        self.bids().next()
+    }
+
+    /// Lowest ask, as `(price, qty)`.
+    pub fn best_ask(&self) -> Option<(Price, Qty)> {
+        self.asks().next()
+    }
+
+    /// Number of price levels on `side`.
+    pub fn depth(&self, side: &Side) -> usize {
+        match side {
+            Side::Buy => self.bids.len(),
+            Side::Sell => self.asks.len(),
+            Side::Other(_) => 0,
+        }
+    }
+
+    /// Bid levels as `(price, qty)`, best first.
+    pub fn bids(&self) -> impl Iterator<Item = (Price, Qty)> + '_ {
+        self.bids.iter().rev().map(|(level, &qty)| (level.0, qty))
+    }
+
+    /// Ask levels as `(price, qty)`, best first.
+    pub fn asks(&self) -> impl Iterator<Item = (Price, Qty)> + '_ {
+        self.asks.iter().map(|(level, &qty)| (level.0, qty))
+    }
+
+    /// Levels of `side` as `(price, qty)`, best first.
+    pub fn levels(&self, side: &Side) -> Box<dyn Iterator<Item = (Price, Qty)> + '_> {
+        match side {
+            Side::Buy => Box::new(self.bids()),
+            Side::Sell => Box::new(self.asks()),
+            Side::Other(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    //// analytics ////
+
+    /// Midpoint between the best bid and the best ask.
+    pub fn mid(&self) -> Option<Price> {
+        let ((bid, _), (ask, _)) = self.best_bid().zip(self.best_ask())?;
+        Some((bid + ask) / Price::from(2u8))
+    }
+
+    /// Best ask minus best bid; negative if the book is crossed.
+    pub fn spread(&self) -> Option<Price> {
+        let ((bid, _), (ask, _)) = self.best_bid().zip(self.best_ask())?;
+        Some(ask - bid)
+    }
+
+    /// Spread as a number of ticks of `tick_size`.
+    pub fn spread_ticks(&self, tick_size: Price) -> Option<Price> {
+        let spread = self.spread()?;
+        (tick_size > Price::default()).then(|| spread / tick_size)
+    }
+
+    /// Spread in basis points of the mid price.
+    pub fn spread_bps(&self) -> Option<Price> {
+        let (spread, mid) = self.spread().zip(self.mid())?;
+        (mid > Price::default()).then(|| spread / mid * Price::from(10_000u16))
+    }
+
+    /// Quantity available on `side` at `price` or better.
+    pub fn depth_to(&self, side: &Side, price: Price) -> Qty {
+        let within = |level: Price| match side {
+            Side::Buy => level >= price,
+            _ => level <= price,
+        };
+
+        self.levels(side)
+            .take_while(|&(level, _)| within(level))
+            .fold(Qty::default(), |depth, (_, qty)| depth + qty)
+    }
+
+    /// Volume-weighted average price of filling `qty` against the levels of `side`, best first.
+    ///
+    /// Selling fills against [`Side::Buy`] and buying against [`Side::Sell`]. Returns `None` if
+    /// `side` does not hold enough quantity.
+    pub fn vwap(&self, side: &Side, qty: Qty) -> Option<Price> {
+        if qty <= Qty::default() {
+            return None;
+        }
+
+        let mut remaining = qty;
+        let mut notional = Price::default();
+
+        for (price, available) in self.levels(side) {
+            let fill = available.min(remaining);
+
+            notional += price * fill;
+            remaining -= fill;
+
+            if remaining <= Qty::default() {
+                return Some(notional / qty);
+            }
+        }
+
+        None
+    }
+
+    /// Imbalance of the top `levels` levels of each side, from -1 (only asks) to 1 (only bids).
+    pub fn imbalance(&self, levels: usize) -> Option<Price> {
+        let total = |side| {
+            self.levels(&side)
+                .take(levels)
+                .fold(Qty::default(), |total, (_, qty)| total + qty)
+        };
+
+        let (bids, asks) = (total(Side::Buy), total(Side::Sell));
+        let sum = bids + asks;
+
+        (sum > Qty::default()).then(|| (bids - asks) / sum)
+    }
+
+    /// Returns true if the best bid is above the best ask.
+    pub fn is_crossed(&self) -> bool {
+        self.spread()
+            .is_some_and(|spread| spread < Price::default())
+    }
+
+    /// Returns true if the best bid equals the best ask.
+    pub fn is_locked(&self) -> bool {
+        self.spread()
+            .is_some_and(|spread| spread == Price::default())
+    }
+}
+
+/// Order books maintained by the connection task for every product subscribed to on the `book`
+/// feed.
+///
+/// Books are dropped when the connection is lost, the product unsubscribed or a delta missed, and
+/// rebuilt from the next snapshot. Cloning the handle is cheap; all clones share the same books.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBooks {
+    inner: Arc<Mutex<Books>>,
+}
+
+#[derive(Debug, Default)]
+struct Books {
+    books: BTreeMap<Product, OrderBook>,
+    stats: BTreeMap<Product, SequenceStats>,
+}
+
+impl OrderBooks {
+    /// Returns a copy of the book of `product`.
+    pub fn get(&self, product: &Product) -> Option<OrderBook> {
+        self.with(product, OrderBook::clone)
+    }
+
+    /// Runs `f` on the book of `product` without copying it.
+    ///
+    /// The books cannot be updated while `f` runs, so it should return quickly.
+    pub fn with<R>(&self, product: &Product, f: impl FnOnce(&OrderBook) -> R) -> Option<R> {
+        self.lock().books.get(product).map(f)
+    }
+
+    /// Products that currently have a book.
+    pub fn products(&self) -> Vec<Product> {
+        self.lock().books.keys().cloned().collect()
+    }
+
+    /// Out-of-sequence deltas received for `product` since the client started.
+    pub fn stats(&self, product: &Product) -> SequenceStats {
+        self.lock().stats.get(product).copied().unwrap_or_default()
+    }
+
+    /// Out-of-sequence deltas received for all products since the client started.
+    pub fn total_stats(&self) -> SequenceStats {
+        let mut total = SequenceStats::default();
+
+        for stats in self.lock().stats.values() {
+            total.add(stats);
+        }
+
+        total
+    }
+
+    // Feeds book messages into the matching books. Returns the book that has to be resynced if
+    // `msg` revealed a gap.
+    pub(crate) fn update(&self, msg: &models::Msg) -> Option<BookInvalidated> {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+
+        match msg {
+            models::Msg::BookSnapshot(snapshot) => {
+                let book = OrderBook::new(snapshot);
+                inner.books.insert(book.product.clone(), book);
+            }
+            models::Msg::Book(delta) => {
+                let product = delta.product_id.as_ref()?;
+                let sequence = inner.books.get_mut(product)?.apply(delta);
+                let stats = inner.stats.entry(product.clone()).or_default();
+
+                match sequence {
+                    Sequence::Applied | Sequence::Ignored => {}
+                    Sequence::Duplicate => stats.duplicates += 1,
+                    Sequence::Stale => stats.stale += 1,
+                    Sequence::Gap { expected, received } => {
+                        stats.gaps += 1;
+                        inner.books.remove(product);
+
+                        return Some(BookInvalidated {
+                            product: product.clone(),
+                            expected,
+                            received,
+                        });
+                    }
+                }
+            }
+            models::Msg::Unsubscribed(sub) if sub.header.feed == "book" => {
+                match &sub.header.product_ids {
+                    Some(products) => {
+                        for product in products {
+                            inner.books.remove(product);
+                        }
+                    }
+                    None => inner.books.clear(),
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    pub(crate) fn resynced(&self, product: &Product) {
+        self.lock()
+            .stats
+            .entry(product.clone())
+            .or_default()
+            .resyncs += 1;
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().books.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Books> {
+        // the books are only ever replaced whole or updated level by level, so they stay usable
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNAPSHOT: &str = r#"{
+        "feed": "book_snapshot",
+        "product_id": "PI_XBTUSD",
+        "seq": 10,
+        "timestamp": 1612269825817,
+        "bids": [{"price": 100, "qty": 2}, {"price": 99, "qty": 4}],
+        "asks": [{"price": 101, "qty": 1}, {"price": 102, "qty": 3}]
+    }"#;
+
+    fn msg(json: &str) -> models::Msg {
+        json.parse().unwrap()
+    }
+
+    fn delta(seq: u64, side: &str, price: u32, qty: u32) -> models::Msg {
+        msg(&format!(
+            r#"{{"feed": "book", "product_id": "PI_XBTUSD", "side": "{side}", "seq": {seq},
+                "price": {price}, "qty": {qty}, "timestamp": 1612269825817}}"#
+        ))
+    }
+
+    fn price(value: &str) -> Price {
+        serde_json::from_str(value).unwrap()
+    }
+
+    fn book() -> OrderBook {
+        match msg(SNAPSHOT) {
+            models::Msg::BookSnapshot(snapshot) => OrderBook::new(&snapshot),
+            msg => panic!("not a snapshot: {msg:?}"),
+        }
+    }
+
+    fn books() -> (OrderBooks, Product) {
+        let books = OrderBooks::default();
+        assert!(books.update(&msg(SNAPSHOT)).is_none());
+        (books, Product::from("PI_XBTUSD"))
+    }
+
+    #[test]
+    fn applies_deltas_in_sequence() {
+        let (books, product) = books();
+
+        assert!(books.update(&delta(11, "buy", 100, 5)).is_none());
+        assert!(books.update(&delta(12, "buy", 99, 0)).is_none());
+        assert!(books.update(&delta(13, "sell", 103, 1)).is_none());
+
+        let book = books.get(&product).unwrap();
+        assert_eq!(book.seq(), 13);
+        assert_eq!(book.best_bid(), Some((price("100"), price("5"))));
+        assert_eq!(book.depth(&Side::Buy), 1);
+        assert_eq!(book.depth(&Side::Sell), 3);
+        assert_eq!(books.stats(&product), SequenceStats::default());
+    }
+
+    #[test]
+    fn skips_duplicate_and_stale_deltas() {
+        let (books, product) = books();
+        assert!(books.update(&delta(11, "buy", 100, 5)).is_none());
+
+        assert!(books.update(&delta(11, "buy", 100, 7)).is_none());
+        assert!(books.update(&delta(9, "buy", 100, 8)).is_none());
+
+        let book = books.get(&product).unwrap();
+        assert_eq!(book.seq(), 11);
+        assert_eq!(book.best_bid(), Some((price("100"), price("5"))));
+
+        let stats = books.stats(&product);
+        assert_eq!((stats.duplicates, stats.stale, stats.gaps), (1, 1, 0));
+    }
+
+    #[test]
+    fn gap_drops_the_book() {
+        let (books, product) = books();
+
+        let invalidated = books.update(&delta(13, "buy", 100, 5)).unwrap();
+        assert_eq!(invalidated.product, product);
+        assert_eq!((invalidated.expected, invalidated.received), (11, 13));
+
+        assert!(books.get(&product).is_none());
+        assert_eq!(books.stats(&product).gaps, 1);
+
+        // deltas are ignored until the next snapshot
+        assert!(books.update(&delta(14, "buy", 100, 5)).is_none());
+        assert_eq!(books.total_stats().gaps, 1);
+
+        books.resynced(&product);
+        assert!(books.update(&msg(SNAPSHOT)).is_none());
+        assert_eq!(books.get(&product).unwrap().seq(), 10);
+        assert_eq!(books.stats(&product).resyncs, 1);
+    }
+
+    #[test]
+    fn mid_and_spread() {
+        let book = book();
+
+        assert_eq!(book.mid(), Some(price("100.5")));
+        assert_eq!(book.spread(), Some(price("1")));
+        assert!(!book.is_crossed() && !book.is_locked());
+    }
+
+    #[test]
+    fn depth_to_price() {
+        let book = book();
+
+        assert_eq!(book.depth_to(&Side::Buy, price("100")), price("2"));
+        assert_eq!(book.depth_to(&Side::Buy, price("99")), price("6"));
+        assert_eq!(book.depth_to(&Side::Sell, price("101.5")), price("1"));
+        assert_eq!(book.depth_to(&Side::Sell, price("100")), price("0"));
+    }
+
+    #[test]
+    fn vwap_walks_the_levels() {
+        let book = book();
+
+        assert_eq!(book.vwap(&Side::Sell, price("1")), Some(price("101")));
+        assert_eq!(book.vwap(&Side::Sell, price("2")), Some(price("101.5")));
+        assert_eq!(book.vwap(&Side::Buy, price("4")), Some(price("99.5")));
+        assert_eq!(book.vwap(&Side::Sell, price("5")), None);
+        assert_eq!(book.vwap(&Side::Buy, price("0")), None);
+    }
+
+    #[test]
+    fn imbalance_of_top_levels() {
+        let book = book();
+
+        assert_eq!(book.imbalance(1), Some(price("1") / price("3")));
+        assert_eq!(book.imbalance(2), Some(price("2") / price("10")));
+        assert_eq!(book.imbalance(0), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest as _, handshake::client::Request, http},
+    Connector,
+};
+
+use crate::{
+    connection::{Connection, Reconnect, Shared},
+    queue,
+    stream::Routes,
+    subscriber::Subscribers,
+    Backpressure, Commander, ConnectionState, Error, Lag, OrderBooks, Receiver, Result,
+    TickerCache, WebSocket,
+};
+
+/// Settings shared by the client handle and its connection task.
+pub(crate) struct Config {
+    pub(crate) keys: Option<(String, String)>,
+    pub(crate) send_buffer: usize,
+    pub(crate) receive_buffer: usize,
+    pub(crate) broadcast: Option<usize>,
+    pub(crate) lag: Lag,
+    pub(crate) backpressure: Backpressure,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) subscribe_timeout: Option<Duration>,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) pong_timeout: Option<Duration>,
+    pub(crate) heartbeat_timeout: Option<Duration>,
+    pub(crate) connector: Option<Connector>,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) reconnect: Reconnect,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keys: None,
+            send_buffer: 42,
+            receive_buffer: 42,
+            broadcast: None,
+            lag: Lag::default(),
+            backpressure: Backpressure::default(),
+            connect_timeout: None,
+            subscribe_timeout: None,
+            keepalive: None,
+            pong_timeout: None,
+            heartbeat_timeout: None,
+            connector: None,
+            headers: Vec::new(),
+            reconnect: Reconnect::default(),
+        }
+    }
+}
+
+impl Config {
+    // deadline for answering a keepalive ping; defaults to the keepalive interval
+    pub(crate) fn pong_timeout(&self) -> Option<Duration> {
+        self.keepalive
+            .map(|interval| self.pong_timeout.unwrap_or(interval))
+    }
+
+    // builds the upgrade request, including any extra headers
+    pub(crate) fn request(&self, url: &str) -> Result<Request> {
+        let mut request = url.into_client_request().map_err(Error::connect)?;
+
+        for (name, value) in &self.headers {
+            let name = http::HeaderName::from_bytes(name.as_bytes()).map_err(http::Error::from);
+            let value = http::HeaderValue::from_str(value).map_err(http::Error::from);
+
+            match name.and_then(|name| Ok((name, value?))) {
+                Ok((name, value)) => {
+                    request.headers_mut().append(name, value);
+                }
+                Err(err) => return Err(Error::connect(err.into())),
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+/// Configurable construction of a [`WebSocket`].
+///
+/// ```no_run
+/// # async fn run() -> cf_ws_v1::Result<()> {
+/// use std::time::Duration;
+///
+/// let ws = cf_ws_v1::WebSocket::builder()
+///     .credentials("public key", "private key")
+///     .connect_timeout(Duration::from_secs(10))
+///     .keepalive(Duration::from_secs(30))
+///     .connect("wss://www.cryptofacilities.com/ws/v1")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[must_use]
+pub struct WebSocketBuilder {
+    config: Config,
+}
+
+impl WebSocketBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: Config::default(),
+        }
+    }
+
+    /// API keys used to authenticate private feeds.
+    pub fn credentials(
+        mut self,
+        public_key: impl Into<String>,
+        private_key: impl Into<String>,
+    ) -> Self {
+        self.config.keys = Some((public_key.into(), private_key.into()));
+        self
+    }
+
+    /// Capacity of the queue of outgoing requests. Defaults to 42; 0 is raised to 1.
+    pub fn send_buffer(mut self, capacity: usize) -> Self {
+        self.config.send_buffer = capacity.max(1);
+        self
+    }
+
+    /// Capacity of the queue of received messages, and of each typed stream. Defaults to 42; 0 is
+    /// raised to 1.
+    pub fn receive_buffer(mut self, capacity: usize) -> Self {
+        self.config.receive_buffer = capacity.max(1);
+        self
+    }
+
+    /// What to do with incoming messages while the receive queue is full. Defaults to
+    /// [`Backpressure::Block`].
+    pub fn backpressure(mut self, policy: Backpressure) -> Self {
+        self.config.backpressure = policy;
+        self
+    }
+
+    /// Delivers incoming messages to [subscribers](WebSocket::subscriber) instead of
+    /// [`WebSocket::next_msg`], keeping up to `capacity` messages for subscribers that fall
+    /// behind; 0 is raised to 1.
+    ///
+    /// Failures are delivered to subscribers as [`Msg::Failure`](crate::Msg::Failure), and the
+    /// connection is kept until every [`Commander`] is dropped, whether or not the receiver is
+    /// read. Off by default, in which case subscribers receive nothing.
+    pub fn broadcast(mut self, capacity: usize) -> Self {
+        self.config.broadcast = Some(capacity.max(1));
+        self
+    }
+
+    /// Lag policy of new [subscribers](WebSocket::subscriber). Defaults to [`Lag::Report`].
+    pub fn lag(mut self, lag: Lag) -> Self {
+        self.config.lag = lag;
+        self
+    }
+
+    /// Maximum time allowed to establish each connection, including reconnects.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum time to wait for the server to answer a subscription request; see [`Ack`](crate::Ack).
+    pub fn subscribe_timeout(mut self, timeout: Duration) -> Self {
+        self.config.subscribe_timeout = Some(timeout);
+        self
+    }
+
+    /// Interval at which the client pings the server.
+    ///
+    /// The connection is considered dead, and is reconnected, if a ping is not answered within
+    /// the [pong timeout](Self::pong_timeout).
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.config.keepalive = Some(interval);
+        self
+    }
+
+    /// Deadline for the server to answer a keepalive ping. Defaults to the keepalive interval.
+    pub fn pong_timeout(mut self, timeout: Duration) -> Self {
+        self.config.pong_timeout = Some(timeout);
+        self
+    }
+
+    /// Considers the connection dead, and reconnects, if nothing is received for `timeout`.
+    ///
+    /// The client subscribes to the `heartbeat` feed on every connection so that idle connections
+    /// still receive regular traffic; `timeout` should comfortably exceed the heartbeat period.
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.config.heartbeat_timeout = Some(timeout);
+        self
+    }
+
+    /// TLS connector used instead of the default one.
+    pub fn tls_connector(mut self, connector: Connector) -> Self {
+        self.config.connector = Some(connector);
+        self
+    }
+
+    /// Extra HTTP header sent with the WebSocket upgrade request.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Reconnection behaviour; see [`Reconnect`].
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.config.reconnect = reconnect;
+        self
+    }
+
+    /// Connects to `ws_url` and starts the connection task.
+    pub async fn connect(self, ws_url: &str) -> Result<WebSocket> {
+        let Self { config } = self;
+        let ws_url = ws_url.to_owned();
+
+        let (send_tx, send_rx) = mpsc::channel(config.send_buffer);
+        let (recv_tx, recv_rx) = queue::channel(config.receive_buffer, config.backpressure);
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Connecting);
+        let (private_tx, private_rx) = watch::channel(BTreeSet::new());
+        let books = OrderBooks::default();
+        let tickers = TickerCache::default();
+        let routes = Routes::new(config.receive_buffer);
+        let (broadcast_tx, broadcast_rx) = config.broadcast.map(broadcast::channel).unzip();
+
+        let ws = Connection::connect(&ws_url, &config).await?;
+        state_tx.send_replace(ConnectionState::Connected);
+
+        let has_keys = config.keys.is_some();
+        let subscribe_timeout = config.subscribe_timeout;
+        let lag = config.lag;
+
+        let conn = Connection::new(
+            ws_url,
+            config,
+            send_rx,
+            recv_tx,
+            state_tx,
+            Shared {
+                private_tx,
+                books: books.clone(),
+                tickers: tickers.clone(),
+                routes: routes.clone(),
+                broadcast: broadcast_tx,
+            },
+        );
+        let handle = tokio::spawn(conn.run(ws));
+
+        Ok(WebSocket {
+            commander: Commander {
+                tx: send_tx,
+                state: state_rx,
+                private_feeds: private_rx,
+                books,
+                tickers,
+                routes,
+                subscribers: Subscribers::new(broadcast_rx, lag),
+                has_keys,
+                subscribe_timeout,
+            },
+            receiver: Receiver { rx: recv_rx },
+            handle,
+        })
+    }
+}
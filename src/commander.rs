@@ -0,0 +1,181 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use log::info;
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::Instant,
+};
+
+use crate::{
+    connection::{Command, Subscription},
+    stream::Routes,
+    subscriber::Subscribers,
+    AccountBalancesAndMargins, Ack, BookUpdate, Close, ConnectionState, Error, Feed, FeedStream,
+    Fill, LatestTicker, Notification, OpenPositions, OrderBook, OrderBooks, Product, Result,
+    Subscriber, Ticker, TickerCache, Trade,
+};
+
+/// Cloneable handle that controls a connection while its [`Receiver`](crate::Receiver) is read
+/// elsewhere; see [`WebSocket::split`](crate::WebSocket::split).
+///
+/// The methods match those of [`WebSocket`](crate::WebSocket), but take `&self`, so any number
+/// of tasks can change subscriptions at the same time. The connection is closed once every
+/// commander has been dropped, or the receiver outside
+/// [broadcast mode](crate::WebSocketBuilder::broadcast).
+#[derive(Clone)]
+pub struct Commander {
+    pub(crate) tx: mpsc::Sender<Command>,
+    pub(crate) state: watch::Receiver<ConnectionState>,
+    pub(crate) private_feeds: watch::Receiver<BTreeSet<Feed>>,
+    pub(crate) books: OrderBooks,
+    pub(crate) tickers: TickerCache,
+    pub(crate) routes: Routes,
+    pub(crate) subscribers: Subscribers,
+    pub(crate) has_keys: bool,
+    pub(crate) subscribe_timeout: Option<Duration>,
+}
+
+impl Commander {
+    /// Shuts the connection down; see [`WebSocket::close`](crate::WebSocket::close).
+    ///
+    /// The receiver keeps delivering messages until the connection task has stopped, and must
+    /// be read meanwhile so that the task is not held up by a full receive queue.
+    pub async fn close(&self, close: Close) -> Result<bool> {
+        let (done, clean) = oneshot::channel();
+
+        self.tx
+            .send(Command::Close { close, done })
+            .await
+            .map_err(|_| Error::ChannelClosed)?;
+
+        clean.await.map_err(|_| Error::ChannelClosed)
+    }
+
+    /// Returns the current connection state.
+    pub fn state(&self) -> ConnectionState {
+        self.state.borrow().clone()
+    }
+
+    /// Returns a receiver that is notified of every connection state transition.
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Returns a copy of the local order book of `product`.
+    pub fn order_book(&self, product: &Product) -> Option<OrderBook> {
+        self.books.get(product)
+    }
+
+    /// Returns a handle to the local order books.
+    pub fn order_books(&self) -> OrderBooks {
+        self.books.clone()
+    }
+
+    /// Returns the newest tickers of `product`.
+    pub fn latest_ticker(&self, product: &Product) -> Option<LatestTicker> {
+        self.tickers.get(product)
+    }
+
+    /// Returns a handle to the cache of newest tickers.
+    pub fn ticker_cache(&self) -> TickerCache {
+        self.tickers.clone()
+    }
+
+    /// Returns a new handle that receives every message from now on; see [`Subscriber`].
+    pub fn subscriber(&self) -> Subscriber {
+        self.subscribers.subscribe()
+    }
+
+    //// typed streams ////
+
+    /// Returns a stream of the trades of `product`; see [`WebSocket::trades`](crate::WebSocket::trades).
+    pub fn trades(&self, product: impl Into<Product>) -> FeedStream<Trade> {
+        self.routes.trades(product.into())
+    }
+
+    /// Returns a stream of the tickers of `product`.
+    pub fn tickers(&self, product: impl Into<Product>) -> FeedStream<Ticker> {
+        self.routes.tickers(product.into())
+    }
+
+    /// Returns a stream of the book snapshots and deltas of `product`.
+    pub fn book_updates(&self, product: impl Into<Product>) -> FeedStream<BookUpdate> {
+        self.routes.book_updates(product.into())
+    }
+
+    /// Returns a stream of the account's fills.
+    pub fn fills(&self) -> FeedStream<Fill> {
+        self.routes.fills()
+    }
+
+    /// Returns a stream of the account's open positions.
+    pub fn positions(&self) -> FeedStream<OpenPositions> {
+        self.routes.positions()
+    }
+
+    /// Returns a stream of the account's balances and margins.
+    pub fn balances(&self) -> FeedStream<AccountBalancesAndMargins> {
+        self.routes.balances()
+    }
+
+    /// Returns a stream of the account's notifications.
+    pub fn notifications(&self) -> FeedStream<Notification> {
+        self.routes.notifications()
+    }
+
+    //// subscriptions ////
+
+    /// Subscribes to a feed; see [`WebSocket::subscribe`](crate::WebSocket::subscribe).
+    pub async fn subscribe(&self, feed: Feed, products: Option<&[Product]>) -> Result<Ack> {
+        self.check_keys(feed)?;
+
+        info!("subscribe to feed: {feed}");
+
+        self.send_with_ack("subscribe", Subscription::new(feed, products))
+            .await
+    }
+
+    /// Unsubscribes from a feed, or from some of its products.
+    pub async fn unsubscribe(&self, feed: Feed, products: Option<&[Product]>) -> Result<Ack> {
+        self.check_keys(feed)?;
+
+        info!("unsubscribe from feed: {feed}");
+
+        self.send_with_ack("unsubscribe", Subscription::new(feed, products))
+            .await
+    }
+
+    /// Returns the private feeds the server has confirmed on the current connection.
+    pub fn private_subscriptions(&self) -> BTreeSet<Feed> {
+        self.private_feeds.borrow().clone()
+    }
+
+    fn check_keys(&self, feed: Feed) -> Result<()> {
+        if self.has_keys || !feed.is_private() {
+            Ok(())
+        } else {
+            Err(Error::Auth(format!(
+                "{feed} requires API keys, but none were provided"
+            )))
+        }
+    }
+
+    async fn send_with_ack(&self, event: &'static str, sub: Subscription) -> Result<Ack> {
+        let deadline = self
+            .subscribe_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let (done, ack) = Ack::new(deadline);
+
+        self.tx
+            .send(Command::Send {
+                event,
+                sub,
+                ack: Some(done),
+                deadline,
+            })
+            .await
+            .map_err(|_| Error::ChannelClosed)?;
+
+        Ok(ack)
+    }
+}
@@ -0,0 +1,1205 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::prelude::*;
+use futures_util::{SinkExt as _, StreamExt as _};
+use hmac::{Hmac, Mac as _};
+use rand::Rng as _;
+use sha2::{Digest as _, Sha256, Sha512};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, oneshot, watch},
+    time::{self, Instant, Interval, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    builder::Config, models, queue::QueueSender, stream::Routes, BookInvalidated, ConnectionState,
+    Error, Feed, OrderBooks, Product, Result, TickerCache,
+};
+
+type HmacSha512 = Hmac<Sha512>;
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// wait for the challenge when no subscribe, pong or heartbeat timeout is configured
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// wait for the answers to internal requests when no subscribe timeout is configured
+const RESYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reconnection behaviour used when the connection to the exchange drops.
+///
+/// Delays grow exponentially from `initial_delay` up to `max_delay`, and each one is randomly
+/// shortened or lengthened by up to `jitter` (a fraction between 0.0 and 1.0).
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+
+    /// Consecutive failed attempts after which the client gives up; `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Reconnect {
+    /// Never reconnect; the client stops once the connection drops.
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let exp = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let delay = self.initial_delay.as_secs_f64() * exp;
+        let delay = delay.min(self.max_delay.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * rand::thread_rng().gen_range(-1.0..=1.0);
+
+        // nonsensical settings, such as a negative multiplier, must not panic the connection task
+        Duration::try_from_secs_f64((delay * factor).max(0.0)).unwrap_or(self.max_delay)
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+/// How [`WebSocket::close`](crate::WebSocket::close) shuts the connection down.
+#[derive(Debug, Clone)]
+pub struct Close {
+    /// Reason sent in the close frame.
+    pub reason: String,
+
+    /// Unsubscribe from every feed before closing.
+    pub unsubscribe: bool,
+
+    /// How long to wait for the server to answer the close frame.
+    pub timeout: Duration,
+}
+
+impl Close {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for Close {
+    fn default() -> Self {
+        Self {
+            reason: String::new(),
+            unsubscribe: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Subscription {
+    pub(crate) feed: Feed,
+    pub(crate) product_ids: Option<Vec<Product>>,
+}
+
+impl Subscription {
+    pub(crate) fn new(feed: Feed, products: Option<&[Product]>) -> Self {
+        if products.is_some() && !feed.takes_products() {
+            log::warn!("{feed} does not take product IDs; ignoring them");
+        }
+
+        let products = products.filter(|_| feed.takes_products());
+
+        Self {
+            feed,
+            product_ids: products.map(<[_]>::to_vec),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Command {
+    Send {
+        event: &'static str,
+        sub: Subscription,
+        ack: Option<oneshot::Sender<Result<()>>>,
+        // when `ack` times out
+        deadline: Option<Instant>,
+    },
+    Close {
+        close: Close,
+        done: oneshot::Sender<bool>,
+    },
+}
+
+// subscriptions to replay after a reconnect
+#[derive(Debug, Default)]
+struct Subscriptions {
+    public: BTreeMap<Feed, Option<BTreeSet<Product>>>,
+    private: BTreeSet<Feed>,
+}
+
+impl Subscriptions {
+    fn track(&mut self, event: &str, sub: &Subscription) {
+        match (event, sub.feed.is_private()) {
+            ("subscribe", true) => {
+                self.private.insert(sub.feed);
+            }
+            ("unsubscribe", true) => {
+                self.private.remove(&sub.feed);
+            }
+            ("subscribe", false) => {
+                let products = self.public.entry(sub.feed).or_default();
+
+                if let Some(ids) = &sub.product_ids {
+                    products
+                        .get_or_insert_with(Default::default)
+                        .extend(ids.iter().cloned());
+                }
+            }
+            ("unsubscribe", false) => match &sub.product_ids {
+                Some(ids) => {
+                    if let Some(Some(products)) = self.public.get_mut(&sub.feed) {
+                        for id in ids {
+                            products.remove(id);
+                        }
+
+                        if products.is_empty() {
+                            self.public.remove(&sub.feed);
+                        }
+                    }
+                }
+                None => {
+                    self.public.remove(&sub.feed);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn replay(&self) -> impl Iterator<Item = Subscription> + '_ {
+        let private = self.private.iter().map(|&feed| Subscription {
+            feed,
+            product_ids: None,
+        });
+
+        let public = self.public.iter().map(|(&feed, products)| Subscription {
+            feed,
+            product_ids: products.as_ref().map(|ids| ids.iter().cloned().collect()),
+        });
+
+        public.chain(private)
+    }
+}
+
+// subscribe or unsubscribe request waiting for the server's answer
+#[derive(Debug)]
+struct PendingAck {
+    event: &'static str,
+    feed: Feed,
+    // products not acknowledged yet; empty for feeds without products
+    products: BTreeSet<Product>,
+    // `None` for requests the connection task makes itself
+    done: Option<oneshot::Sender<Result<()>>>,
+    // when the request is given up on
+    deadline: Option<Instant>,
+}
+
+// signed challenge for the current socket
+#[derive(Debug)]
+struct Auth {
+    challenge: String,
+    signed_challenge: String,
+}
+
+// why the socket stopped being served
+enum Exit {
+    Disconnected {
+        reason: String,
+        close_code: Option<u16>,
+    },
+    Shutdown,
+}
+
+impl Exit {
+    fn disconnected(reason: impl Into<String>) -> Self {
+        Exit::Disconnected {
+            reason: reason.into(),
+            close_code: None,
+        }
+    }
+}
+
+/// Handles through which the connection task publishes to the client.
+pub(crate) struct Shared {
+    pub(crate) private_tx: watch::Sender<BTreeSet<Feed>>,
+    pub(crate) books: OrderBooks,
+    pub(crate) tickers: TickerCache,
+    pub(crate) routes: Routes,
+    pub(crate) broadcast: Option<broadcast::Sender<Arc<models::Msg>>>,
+}
+
+/// Reconnect supervisor; owns the socket and everything needed to restore it.
+pub(crate) struct Connection {
+    url: String,
+    config: Config,
+    cmd_rx: mpsc::Receiver<Command>,
+    msg_tx: QueueSender,
+    state_tx: watch::Sender<ConnectionState>,
+    // private feeds the server has confirmed on the current socket
+    private_tx: watch::Sender<BTreeSet<Feed>>,
+    books: OrderBooks,
+    tickers: TickerCache,
+    routes: Routes,
+    // set in broadcast mode
+    broadcast: Option<broadcast::Sender<Arc<models::Msg>>>,
+    // books that missed deltas and need a fresh snapshot
+    invalidated_books: Vec<BookInvalidated>,
+    subscriptions: Subscriptions,
+    pending_acks: VecDeque<PendingAck>,
+    auth: Option<Auth>,
+    // set while waiting for a challenge, so that an error about it is not taken for an answer
+    // to a subscription
+    challenging: bool,
+
+    // liveness of the current socket
+    last_seen: Instant,
+    pong_deadline: Option<Instant>,
+}
+
+impl Connection {
+    pub(crate) fn new(
+        url: String,
+        config: Config,
+        cmd_rx: mpsc::Receiver<Command>,
+        msg_tx: QueueSender,
+        state_tx: watch::Sender<ConnectionState>,
+        shared: Shared,
+    ) -> Self {
+        let Shared {
+            private_tx,
+            books,
+            tickers,
+            routes,
+            broadcast,
+        } = shared;
+
+        Self {
+            url,
+            config,
+            cmd_rx,
+            msg_tx,
+            state_tx,
+            private_tx,
+            books,
+            tickers,
+            routes,
+            broadcast,
+            invalidated_books: Vec::new(),
+            subscriptions: Subscriptions::default(),
+            pending_acks: VecDeque::new(),
+            auth: None,
+            challenging: false,
+            last_seen: Instant::now(),
+            pong_deadline: None,
+        }
+    }
+
+    pub(crate) async fn connect(url: &str, config: &Config) -> Result<Stream> {
+        let request = config.request(url)?;
+        let connect = connect_async_tls_with_config(request, None, false, config.connector.clone());
+
+        let res = match config.connect_timeout {
+            Some(timeout) => time::timeout(timeout, connect)
+                .await
+                .map_err(|_| Error::Timeout("connect"))?,
+            None => connect.await,
+        };
+
+        let (ws, _res) = res.map_err(Error::connect)?;
+        Ok(ws)
+    }
+
+    pub(crate) async fn run(mut self, mut ws: Stream) {
+        self.set_state(ConnectionState::Connected).await;
+
+        loop {
+            match self.serve(&mut ws).await {
+                Exit::Shutdown => break,
+                Exit::Disconnected { reason, close_code } => {
+                    log::warn!("disconnected: {reason}");
+                    self.set_state(ConnectionState::Disconnected { reason, close_code })
+                        .await;
+                }
+            }
+
+            // private feeds have to be authenticated and confirmed again on the next socket
+            self.auth = None;
+            self.private_tx
+                .send_if_modified(|feeds| !std::mem::take(feeds).is_empty());
+            self.books.clear();
+            self.invalidated_books.clear();
+            // internal requests are not answered on the next socket
+            self.expire();
+            self.pending_acks.retain(|ack| ack.done.is_some());
+
+            match self.reconnect().await {
+                Some(new) => ws = new,
+                None => break,
+            }
+        }
+
+        // the state must not claim a socket once nothing serves it any more
+        if !matches!(
+            *self.state_tx.borrow(),
+            ConnectionState::Disconnected { .. }
+        ) {
+            self.set_state(ConnectionState::Disconnected {
+                reason: "connection closed".to_owned(),
+                close_code: None,
+            })
+            .await;
+        }
+
+        self.routes.close();
+        log::warn!("WS management task is done");
+    }
+
+    async fn serve(&mut self, ws: &mut Stream) -> Exit {
+        let mut keepalive = self.config.keepalive.map(|period| {
+            let mut interval = time::interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+
+        self.last_seen = Instant::now();
+        self.pong_deadline = None;
+
+        // the heartbeat feed guarantees traffic for the watchdog on otherwise idle connections
+        if self.config.heartbeat_timeout.is_some() {
+            let heartbeat = Subscription::new(Feed::Heartbeat, None);
+
+            if let Err(exit) = self.send_subscription(ws, "subscribe", &heartbeat).await {
+                return exit;
+            }
+        }
+
+        loop {
+            log::trace!("waiting for message or event");
+
+            let watchdog = self
+                .config
+                .heartbeat_timeout
+                .map(|timeout| self.last_seen + timeout);
+            let deadline = self.pong_deadline.into_iter().chain(watchdog).min();
+
+            tokio::select! {
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    return self.stale();
+                }
+
+                _ = tick(&mut keepalive) => {
+                    log::trace!("keepalive; sending PING");
+
+                    if let Err(exit) = self.send(ws, Message::Ping(Vec::new())).await {
+                        return exit;
+                    }
+
+                    if self.pong_deadline.is_none() {
+                        self.pong_deadline = self.config.pong_timeout().map(|timeout| Instant::now() + timeout);
+                    }
+                }
+
+                cmd = self.cmd_rx.recv() => {
+                    let Some(cmd) = cmd else {
+                        log::debug!("client handle dropped; closing connection");
+                        let _ = ws.close(None).await;
+                        return Exit::Shutdown;
+                    };
+
+                    if let Err(exit) = self.handle_command(ws, cmd).await {
+                        return exit;
+                    }
+                }
+
+                res = ws.next() => {
+                    let msg = match res {
+                        Some(Ok(msg)) => msg,
+                        Some(Err(err)) => {
+                            log::error!("{err}");
+                            return self.fail(Error::protocol(err)).await;
+                        }
+                        None => return Exit::disconnected("connection closed by server"),
+                    };
+
+                    match self.handle_frame(ws, msg).await {
+                        Ok(Some(msg)) => {
+                            if !self.forward(msg).await {
+                                log::debug!("receiver dropped; closing connection");
+                                let _ = ws.close(None).await;
+                                return Exit::Shutdown;
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(exit) => return exit,
+                    }
+
+                    if let Err(exit) = self.resync_books(ws).await {
+                        return exit;
+                    }
+                }
+            };
+        }
+    }
+
+    async fn handle_command(&mut self, ws: &mut Stream, cmd: Command) -> Result<(), Exit> {
+        match cmd {
+            Command::Send {
+                event,
+                sub,
+                ack,
+                deadline,
+            } => {
+                self.subscriptions.track(event, &sub);
+                self.expect_ack(event, &sub, ack, deadline);
+                self.send_subscription(ws, event, &sub).await
+            }
+            Command::Close { close, done } => {
+                let clean = self.close(ws, close).await;
+                let _ = done.send(clean);
+                Err(Exit::Shutdown)
+            }
+        }
+    }
+
+    // Queues the answer expected for a request. Internal requests are queued too, without
+    // `done`, so that their answers are not taken for those of the client's requests.
+    fn expect_ack(
+        &mut self,
+        event: &'static str,
+        sub: &Subscription,
+        done: Option<oneshot::Sender<Result<()>>>,
+        deadline: Option<Instant>,
+    ) {
+        self.pending_acks.push_back(PendingAck {
+            event,
+            feed: sub.feed,
+            products: sub.product_ids.iter().flatten().cloned().collect(),
+            done,
+            deadline,
+        });
+    }
+
+    // Gives up on requests that were not answered in time, or whose `Ack` was dropped, so that
+    // they cannot be matched with answers to later ones. Subscriptions that timed out are not
+    // replayed.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let timed_out = |ack: &PendingAck| ack.deadline.is_some_and(|deadline| deadline <= now);
+
+        let (expired, pending) = std::mem::take(&mut self.pending_acks)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|ack| {
+                timed_out(ack) || ack.done.as_ref().is_some_and(oneshot::Sender::is_closed)
+            });
+
+        self.pending_acks = pending;
+
+        for ack in expired {
+            log::debug!("no answer to {} {} in time", ack.event, ack.feed);
+
+            if timed_out(&ack) && ack.done.is_some() {
+                self.untrack(&ack);
+            }
+        }
+    }
+
+    // Resolves pending acknowledgements answered by `msg`.
+    fn acknowledge(&mut self, msg: &models::Msg) {
+        self.expire();
+
+        match msg {
+            models::Msg::Subscribed(sub) => {
+                if let Ok(feed) = sub.header.feed.parse::<Feed>() {
+                    if self.subscriptions.private.contains(&feed) {
+                        self.private_tx.send_if_modified(|feeds| feeds.insert(feed));
+                    }
+                }
+
+                self.confirm("subscribe", &sub.header);
+            }
+            models::Msg::Unsubscribed(sub) => {
+                if let Ok(feed) = sub.header.feed.parse::<Feed>() {
+                    self.private_tx
+                        .send_if_modified(|feeds| feeds.remove(&feed));
+                }
+
+                self.confirm("unsubscribe", &sub.header);
+            }
+            models::Msg::SubscribedFailed(failed) => self.reject("subscribe", failed),
+            models::Msg::UnsubscribedFailed(failed) => self.reject("unsubscribe", failed),
+
+            // errors do not say which request they are about, unless only one is outstanding
+            models::Msg::Error(err) if !self.challenging && self.pending_acks.len() == 1 => {
+                let ack = self
+                    .pending_acks
+                    .pop_front()
+                    .expect("one request is pending");
+                self.refuse(ack, &err.message);
+            }
+
+            _ => {}
+        }
+    }
+
+    // resolves the pending request once every product it named has been confirmed
+    fn confirm(&mut self, event: &str, header: &models::Header) {
+        let feed = &header.feed;
+        let products = header.product_ids.as_deref().unwrap_or_default();
+
+        let Some(idx) = self.pending_acks.iter().position(|ack| {
+            ack.event == event
+                && ack.feed.as_str() == feed
+                && (products.is_empty() || products.iter().any(|id| ack.products.contains(id)))
+        }) else {
+            return;
+        };
+
+        let ack = &mut self.pending_acks[idx];
+
+        if products.is_empty() {
+            ack.products.clear();
+        } else {
+            for id in products {
+                ack.products.remove(id);
+            }
+        }
+
+        if ack.products.is_empty() {
+            let ack = self.pending_acks.remove(idx).expect("index is in bounds");
+
+            if let Some(done) = ack.done {
+                let _ = done.send(Ok(()));
+            }
+        }
+    }
+
+    // fails pending requests for private feeds that could not be authenticated
+    fn abandon(&mut self, feeds: &BTreeSet<Feed>, reason: &str) {
+        let (failed, pending) = std::mem::take(&mut self.pending_acks)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|ack| feeds.contains(&ack.feed));
+
+        self.pending_acks = pending;
+
+        // the server never saw the requests, so there is nothing to replay
+        self.subscriptions
+            .private
+            .retain(|feed| !feeds.contains(feed));
+
+        for done in failed.into_iter().filter_map(|ack| ack.done) {
+            let _ = done.send(Err(Error::Auth(reason.to_owned())));
+        }
+    }
+
+    // failures may not name the feed, but the server answers requests in order
+    fn reject(&mut self, event: &str, failed: &models::SubscriptionFailed) {
+        let idx = match &failed.feed {
+            Some(feed) => self
+                .pending_acks
+                .iter()
+                .position(|ack| ack.event == event && ack.feed.as_str() == feed),
+            None => (!self.pending_acks.is_empty()).then_some(0),
+        };
+
+        if let Some(idx) = idx {
+            let ack = self.pending_acks.remove(idx).expect("index is in bounds");
+            let reason = failed.message.as_deref().unwrap_or(match event {
+                "unsubscribe" => "unsubscribed_failed",
+                _ => "subscribed_failed",
+            });
+            self.refuse(ack, reason);
+        } else if let Some(feed) = failed.feed.as_ref().and_then(|feed| feed.parse().ok()) {
+            // the request was given up on, but still must not be replayed
+            if event == "subscribe" {
+                let sub = Subscription {
+                    feed,
+                    product_ids: failed.product_ids.clone(),
+                };
+
+                self.subscriptions.track("unsubscribe", &sub);
+            }
+        }
+    }
+
+    // fails a request the server turned down; rejected subscriptions are not replayed
+    fn refuse(&mut self, ack: PendingAck, reason: &str) {
+        self.untrack(&ack);
+
+        if let Some(done) = ack.done {
+            let _ = done.send(Err(Error::Subscribe {
+                feed: ack.feed.to_string(),
+                reason: reason.to_owned(),
+            }));
+        }
+    }
+
+    // forgets a subscription that the server did not confirm
+    fn untrack(&mut self, ack: &PendingAck) {
+        if ack.event == "subscribe" {
+            let sub = Subscription {
+                feed: ack.feed,
+                product_ids: (!ack.products.is_empty())
+                    .then(|| ack.products.iter().cloned().collect()),
+            };
+
+            self.subscriptions.track("unsubscribe", &sub);
+        }
+    }
+
+    // performs the close handshake, returning whether the server answered in time
+    async fn close(&mut self, ws: &mut Stream, close: Close) -> bool {
+        if close.unsubscribe {
+            let subs = self.subscriptions.replay().collect::<Vec<_>>();
+
+            for sub in subs {
+                log::debug!("unsubscribing from {}", sub.feed);
+
+                if self
+                    .send_subscription(ws, "unsubscribe", &sub)
+                    .await
+                    .is_err()
+                {
+                    return false;
+                }
+            }
+        }
+
+        let frame = CloseFrame {
+            code: CloseCode::Normal,
+            reason: close.reason.into(),
+        };
+
+        if let Err(err) = ws.close(Some(frame)).await {
+            log::warn!("could not send CLOSE: {err}");
+            return false;
+        }
+
+        let ack = async {
+            while let Some(msg) = ws.next().await {
+                match msg {
+                    Ok(Message::Close(msg)) => {
+                        log::debug!("CLOSE acknowledged: {msg:X?}");
+                        return true;
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+
+            false
+        };
+
+        match time::timeout(close.timeout, ack).await {
+            Ok(clean) => clean,
+            Err(_) => {
+                log::warn!("server did not answer CLOSE within {:?}", close.timeout);
+                false
+            }
+        }
+    }
+
+    // Handles a single frame, returning the decoded message if it should be passed on to the
+    // consumer, or the exit reason if the socket is no longer usable.
+    async fn handle_frame(
+        &mut self,
+        ws: &mut Stream,
+        msg: Message,
+    ) -> Result<Option<Result<models::Msg>>, Exit> {
+        self.last_seen = Instant::now();
+
+        match msg {
+            Message::Text(text) => {
+                log::debug!("TEXT received: {} bytes", text.len());
+                log::trace!("TEXT received: {text}");
+
+                let msg = text
+                    .parse::<models::Msg>()
+                    .map_err(|source| Error::Json { source, text });
+
+                if let Ok(msg) = &msg {
+                    self.acknowledge(msg);
+                    self.invalidated_books.extend(self.books.update(msg));
+                    self.tickers.update(msg);
+                }
+
+                Ok(Some(msg))
+            }
+            Message::Binary(data) => {
+                log::warn!(
+                    "BINARY message received; not supported: {} bytes",
+                    data.len()
+                );
+                Ok(None)
+            }
+            Message::Ping(msg) => {
+                log::trace!("PING received; sending PONG");
+                self.send(ws, Message::Pong(msg)).await?;
+                Ok(None)
+            }
+            Message::Pong(msg) => {
+                log::debug!("PONG received: {msg:X?}");
+                self.pong_deadline = None;
+                Ok(None)
+            }
+            Message::Close(msg) => {
+                log::debug!("CLOSE received: {msg:X?}");
+                let exit = match &msg {
+                    Some(frame) => Exit::Disconnected {
+                        reason: frame.reason.to_string(),
+                        close_code: Some(frame.code.into()),
+                    },
+                    None => Exit::disconnected("close frame received"),
+                };
+                let _ = ws.close(msg).await;
+                Err(exit)
+            }
+            Message::Frame(_) => unreachable!("raw frames are not exposed here"),
+        }
+    }
+
+    async fn send(&mut self, ws: &mut Stream, msg: Message) -> Result<(), Exit> {
+        log::trace!("sending: {msg:?}");
+
+        match ws.send(msg).await {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.fail(Error::protocol(err)).await),
+        }
+    }
+
+    async fn send_subscription(
+        &mut self,
+        ws: &mut Stream,
+        event: &str,
+        sub: &Subscription,
+    ) -> Result<(), Exit> {
+        if sub.feed.is_private() && self.auth.is_none() {
+            if let Err(reason) = self.login(ws).await? {
+                // the failure itself has already been reported to the consumer
+                self.abandon(&BTreeSet::from([sub.feed]), &reason);
+                return Ok(());
+            }
+        }
+
+        let product_ids = sub
+            .product_ids
+            .as_ref()
+            .map(|ids| ids.iter().map(Product::as_str).collect::<Vec<_>>());
+
+        let auth = self.auth.as_ref().filter(|_| sub.feed.is_private());
+
+        let msg = text_frame(&models::SubscribeMsg {
+            event,
+            feed: sub.feed.as_str(),
+            product_ids: product_ids.as_deref(),
+            api_key: auth.and(self.config.keys.as_ref()).map(|(pb, _)| &**pb),
+            original_challenge: auth.map(|auth| &*auth.challenge),
+            signed_challenge: auth.map(|auth| &*auth.signed_challenge),
+        });
+
+        self.send(ws, msg).await
+    }
+
+    // authenticates the current socket, failing with the reason if it cannot be
+    async fn login(&mut self, ws: &mut Stream) -> Result<Result<(), String>, Exit> {
+        let auth = match self.authenticate(ws).await? {
+            Ok(auth) => auth,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        self.auth = Some(auth);
+        self.set_state(ConnectionState::Authenticated).await;
+        Ok(Ok(()))
+    }
+
+    // Requests and signs a challenge for the current socket. Fails with the reason if the socket
+    // is still usable, but cannot be authenticated.
+    async fn authenticate(&mut self, ws: &mut Stream) -> Result<Result<Auth, String>, Exit> {
+        let Some((public_key, private_key)) = self.config.keys.clone() else {
+            return Ok(Err("no API keys".to_owned()));
+        };
+
+        let msg = text_frame(&models::ChallengeMsg {
+            event: "challenge",
+            api_key: &public_key,
+        });
+
+        self.send(ws, msg).await?;
+
+        // Pings and the heartbeat watchdog are not served while waiting, so the wait is always
+        // bounded. A socket that stays silent is treated as dead, and restored like any other.
+        let timeout = self
+            .config
+            .subscribe_timeout
+            .or_else(|| self.config.pong_timeout())
+            .or(self.config.heartbeat_timeout)
+            .unwrap_or(CHALLENGE_TIMEOUT);
+
+        let requested = Instant::now();
+        self.challenging = true;
+        let res = time::timeout(timeout, self.wait_for_challenge(ws)).await;
+        self.challenging = false;
+
+        let challenge = match res {
+            Ok(Ok(Ok(challenge))) => challenge,
+            Ok(Ok(Err(reason))) => return Ok(Err(reason)),
+            Ok(Err(exit)) => return Err(exit),
+            Err(_) if self.last_seen > requested => {
+                log::error!("no challenge received within {timeout:?}");
+
+                if !self.forward(Err(Error::Timeout("challenge"))).await {
+                    return Err(Exit::Shutdown);
+                }
+
+                return Ok(Err("challenge timed out".to_owned()));
+            }
+            Err(_) => {
+                log::error!("timed out waiting for challenge");
+                return Err(self.fail(Error::Timeout("challenge")).await);
+            }
+        };
+        log::debug!("found challenge: {challenge}");
+
+        match sign(&private_key, &challenge) {
+            Ok(signed_challenge) => Ok(Ok(Auth {
+                challenge,
+                signed_challenge,
+            })),
+            Err(err) => {
+                log::error!("{err}");
+                let reason = err.to_string();
+                self.forward(Err(err)).await;
+                Ok(Err(reason))
+            }
+        }
+    }
+
+    // Waits until challenge event arrives, passing other messages on to the consumer. Fails with
+    // the server's message if it answers with an error instead.
+    async fn wait_for_challenge(
+        &mut self,
+        ws: &mut Stream,
+    ) -> Result<Result<String, String>, Exit> {
+        log::info!("waiting for challenge");
+
+        loop {
+            let msg = match ws.next().await {
+                Some(Ok(msg)) => msg,
+                Some(Err(err)) => return Err(self.fail(Error::protocol(err)).await),
+                None => return Err(Exit::disconnected("connection closed by server")),
+            };
+
+            let msg = match self.handle_frame(ws, msg).await? {
+                Some(Ok(models::Msg::Challenge(c))) => return Ok(Ok(c.message)),
+                Some(msg) => msg,
+                None => continue,
+            };
+
+            // e.g. for an unknown API key; the error itself is passed on like any other message
+            let refused = match &msg {
+                Ok(models::Msg::Error(err)) => Some(err.message.clone()),
+                _ => None,
+            };
+
+            // market data keeps flowing to the consumer, in order, during the handshake
+            if !self.forward(msg).await {
+                return Err(Exit::Shutdown);
+            }
+
+            if let Some(reason) = refused {
+                return Ok(Err(reason));
+            }
+        }
+    }
+
+    // re-establishes the socket, backing off between failed attempts
+    async fn reconnect(&mut self) -> Option<Stream> {
+        let mut attempt = 0;
+
+        loop {
+            if self.consumer_gone() {
+                return None;
+            }
+
+            attempt += 1;
+
+            let max_attempts = self.config.reconnect.max_attempts;
+
+            // with reconnects disabled, the disconnect itself has already been reported
+            if max_attempts == Some(0) {
+                log::info!("reconnects are disabled");
+                return None;
+            }
+
+            if max_attempts.is_some_and(|max| attempt > max) {
+                let reason = format!("gave up reconnecting after {} attempts", attempt - 1);
+                log::error!("{reason}");
+                self.set_state(ConnectionState::Disconnected {
+                    reason,
+                    close_code: None,
+                })
+                .await;
+                return None;
+            }
+
+            self.set_state(ConnectionState::Reconnecting { attempt })
+                .await;
+
+            let delay = self.config.reconnect.delay(attempt);
+            log::info!("reconnecting in {delay:?} (attempt {attempt})");
+
+            if !self.backoff(delay).await {
+                return None;
+            }
+
+            let mut ws = match Self::connect(&self.url, &self.config).await {
+                Ok(ws) => ws,
+                Err(err) => {
+                    log::warn!("reconnect attempt {attempt} failed: {err}");
+                    continue;
+                }
+            };
+
+            self.set_state(ConnectionState::Connected).await;
+
+            match self.resume(&mut ws).await {
+                Ok(()) => {
+                    log::info!("reconnected after {attempt} attempt(s)");
+                    return Some(ws);
+                }
+                Err(Exit::Shutdown) => return None,
+                Err(Exit::Disconnected { reason, close_code }) => {
+                    log::warn!("reconnect attempt {attempt} dropped while restoring subscriptions");
+                    self.set_state(ConnectionState::Disconnected { reason, close_code })
+                        .await;
+                }
+            }
+        }
+    }
+
+    // Waits before a reconnect attempt while still accepting commands, so subscriptions made in
+    // the meantime are replayed and a close request is not held up. Returns false on shutdown.
+    async fn backoff(&mut self, delay: Duration) -> bool {
+        let sleep = time::sleep(delay);
+        tokio::pin!(sleep);
+
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return true,
+
+                cmd = self.cmd_rx.recv() => match cmd {
+                    Some(Command::Send { event, sub, ack, deadline }) => {
+                        self.subscriptions.track(event, &sub);
+                        self.expect_ack(event, &sub, ack, deadline);
+                    }
+                    Some(Command::Close { done, .. }) => {
+                        let _ = done.send(false);
+                        return false;
+                    }
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    // reports books that missed deltas and resubscribes to them to get fresh snapshots
+    async fn resync_books(&mut self, ws: &mut Stream) -> Result<(), Exit> {
+        for invalidated in std::mem::take(&mut self.invalidated_books) {
+            log::warn!(
+                "book of {} expected seq {} but received {}; resyncing",
+                invalidated.product,
+                invalidated.expected,
+                invalidated.received
+            );
+
+            let product = invalidated.product.clone();
+            let msg = models::Msg::BookInvalidated(invalidated);
+
+            if !self.forward(Ok(msg)).await {
+                return Err(Exit::Shutdown);
+            }
+
+            // products that are no longer subscribed to are left alone
+            let subscribed = match self.subscriptions.public.get(&Feed::Book) {
+                Some(Some(products)) => products.contains(&product),
+                Some(None) => true,
+                None => false,
+            };
+
+            if !subscribed {
+                continue;
+            }
+
+            let sub = Subscription::new(Feed::Book, Some(std::slice::from_ref(&product)));
+
+            let timeout = self.config.subscribe_timeout.unwrap_or(RESYNC_TIMEOUT);
+
+            for event in ["unsubscribe", "subscribe"] {
+                self.expect_ack(event, &sub, None, Some(Instant::now() + timeout));
+                self.send_subscription(ws, event, &sub).await?;
+            }
+            self.books.resynced(&product);
+        }
+
+        Ok(())
+    }
+
+    // Replays every tracked subscription on a fresh socket. Private feeds come last, so that
+    // public ones are restored even if the socket cannot be authenticated any more, in which
+    // case the private feeds are dropped.
+    async fn resume(&mut self, ws: &mut Stream) -> Result<(), Exit> {
+        let subs = self.subscriptions.replay().collect::<Vec<_>>();
+
+        for sub in subs {
+            if sub.feed.is_private() && self.auth.is_none() {
+                if let Err(reason) = self.login(ws).await? {
+                    log::error!("could not resubscribe to private feeds: {reason}");
+
+                    let feeds = self.subscriptions.private.clone();
+                    self.abandon(&feeds, &reason);
+
+                    if !self.forward(Err(Error::Auth(reason))).await {
+                        return Err(Exit::Shutdown);
+                    }
+
+                    break;
+                }
+            }
+
+            log::debug!("resubscribing to {}", sub.feed);
+            self.send_subscription(ws, "subscribe", &sub).await?;
+        }
+
+        Ok(())
+    }
+
+    // declares the socket dead after a missed pong or a silent period
+    fn stale(&self) -> Exit {
+        let now = Instant::now();
+
+        match (self.pong_deadline, self.config.heartbeat_timeout) {
+            (Some(deadline), _) if deadline <= now => {
+                log::warn!("PONG not received in time; dropping connection");
+                Exit::disconnected("pong timeout")
+            }
+            (_, Some(timeout)) => {
+                log::warn!("no message received for {timeout:?}; dropping connection");
+                Exit::disconnected("heartbeat timeout")
+            }
+            _ => unreachable!("stale() called without an elapsed deadline"),
+        }
+    }
+
+    // Passes a message on to the streams it is routed to, or else to the subscribers, or else to
+    // the consumer. Returns false once the consumer is gone; in broadcast mode the consumer is
+    // not read, so it never is.
+    async fn forward(&mut self, msg: Result<models::Msg>) -> bool {
+        let msg = match msg {
+            Ok(msg) => self.routes.dispatch(msg),
+            // subscribers have no other way to learn of failures
+            Err(err) if self.broadcast.is_some() => Some(models::Msg::Failure(Arc::new(err))),
+            Err(err) => return self.msg_tx.send(Err(err)).await.is_ok(),
+        };
+
+        match msg.and_then(|msg| self.publish(msg)) {
+            Some(msg) => self.msg_tx.send(Ok(msg)).await.is_ok(),
+            None => true,
+        }
+    }
+
+    // hands `msg` to the subscribers in broadcast mode; returns it back otherwise
+    fn publish(&self, msg: models::Msg) -> Option<models::Msg> {
+        let Some(broadcast) = &self.broadcast else {
+            return Some(msg);
+        };
+
+        // the client handles keep a receiver, so this only fails once they are all gone
+        let _ = broadcast.send(Arc::new(msg));
+        None
+    }
+
+    // reports a socket failure to the consumer
+    async fn fail(&mut self, err: Error) -> Exit {
+        let reason = err.to_string();
+
+        if self.forward(Err(err)).await {
+            Exit::disconnected(reason)
+        } else {
+            Exit::Shutdown
+        }
+    }
+
+    // the consumer only matters outside broadcast mode
+    fn consumer_gone(&self) -> bool {
+        self.broadcast.is_none() && self.msg_tx.is_closed()
+    }
+
+    // publishes a state transition, both on the watch channel and in-band
+    async fn set_state(&mut self, state: ConnectionState) {
+        log::debug!("connection state: {state:?}");
+
+        self.state_tx.send_replace(state.clone());
+        self.forward(Ok(models::Msg::ConnectionState(state))).await;
+    }
+}
+
+// completes on the next tick, or never if there is no interval
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn hmac(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut signer = HmacSha512::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    signer.update(data);
+    signer.finalize().into_bytes().to_vec()
+}
+
+fn sign(private_key: &str, challenge: &str) -> Result<String> {
+    let challenge_hash = Sha256::digest(challenge);
+    let secret = BASE64_STANDARD
+        .decode(private_key)
+        .map_err(|err| Error::Auth(format!("private key is not valid base64: {err}")))?;
+    let digest = hmac(&secret, &challenge_hash);
+    Ok(BASE64_STANDARD.encode(digest))
+}
+
+pub(crate) fn text_frame<T: serde::Serialize>(msg: &T) -> Message {
+    Message::Text(serde_json::to_string(msg).expect("request serialization is infallible"))
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+
+use tokio_tungstenite::tungstenite::{self, error::TlsError};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by the WebSocket client.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The WebSocket connection could not be established.
+    Connect(Box<tungstenite::Error>),
+
+    /// TLS negotiation failed while connecting.
+    Tls(TlsError),
+
+    /// The established connection failed at the WebSocket protocol or transport level.
+    Protocol(Box<tungstenite::Error>),
+
+    /// A text frame received from the server could not be decoded.
+    Json {
+        source: serde_json::Error,
+        text: String,
+    },
+
+    /// Private feed authentication could not be performed.
+    Auth(String),
+
+    /// The server rejected a subscription request.
+    Subscribe { feed: String, reason: String },
+
+    /// The named operation did not complete within its configured timeout.
+    Timeout(&'static str),
+
+    /// A [`Subscriber`](crate::Subscriber) fell behind and missed this many messages.
+    Lagged(u64),
+
+    /// The background connection task has stopped.
+    ChannelClosed,
+}
+
+impl Error {
+    pub(crate) fn protocol(err: tungstenite::Error) -> Self {
+        Error::Protocol(Box::new(err))
+    }
+
+    pub(crate) fn connect(err: tungstenite::Error) -> Self {
+        match err {
+            tungstenite::Error::Tls(err) => Error::Tls(err),
+            err => Error::Connect(Box::new(err)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(err) => write!(f, "connection failed: {err}"),
+            Error::Tls(err) => write!(f, "TLS failure: {err}"),
+            Error::Protocol(err) => write!(f, "WebSocket protocol failure: {err}"),
+            Error::Json { source, text } => write!(f, "could not decode `{text}`: {source}"),
+            Error::Auth(msg) => write!(f, "authentication failed: {msg}"),
+            Error::Subscribe { feed, reason } => {
+                write!(f, "subscription to {feed} rejected: {reason}")
+            }
+            Error::Timeout(op) => write!(f, "{op} timed out"),
+            Error::Lagged(missed) => write!(f, "subscriber missed {missed} messages"),
+            Error::ChannelClosed => f.write_str("connection task has stopped"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect(err) | Error::Protocol(err) => Some(err),
+            Error::Tls(err) => Some(err),
+            Error::Json { source, .. } => Some(source),
+            Error::Auth(_)
+            | Error::Subscribe { .. }
+            | Error::Timeout(_)
+            | Error::Lagged(_)
+            | Error::ChannelClosed => None,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fmt, str::FromStr};
+
+/// A feed of the v1 WebSocket API.
+///
+/// Private feeds require API keys; the client authenticates before subscribing to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Feed {
+    Trade,
+    Book,
+    Ticker,
+    TickerLite,
+    Heartbeat,
+
+    AccountBalancesAndMargins,
+    AccountLog,
+    DepositsWithdrawals,
+    Fills,
+    OpenPositions,
+    OpenOrders,
+    OpenOrdersVerbose,
+    Notifications,
+}
+
+impl Feed {
+    /// Every feed, public ones first.
+    pub const ALL: [Feed; 13] = [
+        Feed::Trade,
+        Feed::Book,
+        Feed::Ticker,
+        Feed::TickerLite,
+        Feed::Heartbeat,
+        Feed::AccountBalancesAndMargins,
+        Feed::AccountLog,
+        Feed::DepositsWithdrawals,
+        Feed::Fills,
+        Feed::OpenPositions,
+        Feed::OpenOrders,
+        Feed::OpenOrdersVerbose,
+        Feed::Notifications,
+    ];
+
+    /// Name of the feed in subscription requests.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feed::Trade => "trade",
+            Feed::Book => "book",
+            Feed::Ticker => "ticker",
+            Feed::TickerLite => "ticker_lite",
+            Feed::Heartbeat => "heartbeat",
+            Feed::AccountBalancesAndMargins => "account_balances_and_margins",
+            Feed::AccountLog => "account_log",
+            Feed::DepositsWithdrawals => "deposits_withdrawals",
+            Feed::Fills => "fills",
+            Feed::OpenPositions => "open_positions",
+            Feed::OpenOrders => "open_orders",
+            Feed::OpenOrdersVerbose => "open_orders_verbose",
+            Feed::Notifications => "notifications_auth",
+        }
+    }
+
+    /// Returns true if the feed requires authentication.
+    pub fn is_private(self) -> bool {
+        !matches!(
+            self,
+            Feed::Trade | Feed::Book | Feed::Ticker | Feed::TickerLite | Feed::Heartbeat
+        )
+    }
+
+    /// Returns true if subscriptions to the feed name the products they are for.
+    pub fn takes_products(self) -> bool {
+        matches!(
+            self,
+            Feed::Trade | Feed::Book | Feed::Ticker | Feed::TickerLite
+        )
+    }
+}
+
+impl fmt::Display for Feed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error returned when parsing an unknown feed name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFeed(pub String);
+
+impl fmt::Display for UnknownFeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown feed `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFeed {}
+
+impl FromStr for Feed {
+    type Err = UnknownFeed;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feed::ALL
+            .into_iter()
+            .find(|feed| feed.as_str() == s)
+            .ok_or_else(|| UnknownFeed(s.to_owned()))
+    }
+}
@@ -2,26 +2,55 @@
 
 #![deny(rust_2018_idioms, nonstandard_style, future_incompatible)]
 
-use base64::prelude::*;
-use futures_util::{SinkExt as _, StreamExt as _};
-use hmac::{Hmac, Mac as _};
-use log::info;
-use sha2::{Digest as _, Sha256, Sha512};
-use tokio::{sync::mpsc, task::JoinHandle};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-
+use std::{
+    collections::BTreeSet,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::{sync::watch, task::JoinHandle};
+
+mod ack;
+mod book;
+mod builder;
+mod commander;
+mod connection;
+mod error;
+mod feed;
 mod models;
+mod product;
+mod queue;
+mod receiver;
+mod state;
+mod stream;
+mod subscriber;
+mod ticker;
+pub use ack::Ack;
+pub use book::{BookInvalidated, OrderBook, OrderBooks, Sequence, SequenceStats};
+pub use builder::WebSocketBuilder;
+pub use commander::Commander;
+pub use connection::{Close, Reconnect};
+pub use error::{Error, Result};
+pub use feed::{Feed, UnknownFeed};
 pub use models::*;
-
-type HmacSha512 = Hmac<Sha512>;
-
+pub use product::{Expiry, InvalidProduct, Product, ProductKind};
+pub use queue::{Backpressure, QueueStats};
+pub use receiver::Receiver;
+pub use state::ConnectionState;
+pub use stream::{BookUpdate, FeedStream};
+pub use subscriber::{Lag, Subscriber};
+pub use ticker::{LatestTicker, TickerCache};
+pub use tokio_tungstenite::Connector;
+
+/// Client for the v1 WebSocket API.
+///
+/// Messages are read with [`WebSocket::next_msg`], or through its [`Stream`] implementation,
+/// which ends once the connection has been shut down.
 pub struct WebSocket {
-    tx: mpsc::Sender<Message>,
-    rx: mpsc::Receiver<models::Msg>,
-    keys: Option<(String, String)>,
-    challenge: Option<String>,
-    signed_challenge: Option<String>,
-    _handle: JoinHandle<()>,
+    commander: Commander,
+    receiver: Receiver,
+    handle: JoinHandle<()>,
 }
 
 impl WebSocket {
@@ -29,239 +58,192 @@ impl WebSocket {
         ws_url: &str,
         public_key: Option<&str>,
         private_key: Option<&str>,
-    ) -> WebSocket {
-        let ws_url = ws_url.to_owned();
-
-        let (send_tx, mut send_rx) = mpsc::channel(42);
-        let (recv_tx, recv_rx) = mpsc::channel(42);
-
-        let (mut ws, _res) = connect_async(&ws_url).await.unwrap();
-
-        let handle = tokio::spawn(async move {
-            loop {
-                log::trace!("waiting for message or event");
-
-                tokio::select! {
-                    msg = send_rx.recv() => {
-                        match msg {
-                            Some(msg) => {
-                                log::trace!("sending TEXT: {msg:?}");
-                                ws.send(msg).await.unwrap();
-                                ws.flush().await.unwrap();
-                            }
-
-                            None => {
-                                panic!("out tx closed ?!");
-                            }
-                        };
-                    }
-
-                    res = ws.next() => {
-                        let msg = match res {
-                            Some(Ok(msg)) => msg,
-                            Some(Err(err)) => {
-                                log::error!("{err}");
-                                continue;
-                            }
-                            None => break,
-                        };
-
-                        match msg {
-                            Message::Text(text) => {
-                                log::debug!("TEXT received: {} bytes", text.len());
-                                log::trace!("TEXT received: {text}");
-
-                                // parse and send to channel
-                                let msg = serde_json::from_str(&text).unwrap();
-                                recv_tx.send(msg).await.unwrap();
-                            }
-                            Message::Binary(_) => {
-                                unimplemented!("BINARY message received; not supported");
-                            }
-                            Message::Ping(msg) => {
-                                log::trace!("PING received; sending PONG");
-                                ws.send(Message::Pong(msg)).await.unwrap();
-                            }
-                            Message::Pong(msg) => {
-                                log::debug!("PONG received: {msg:X?}");
-                            },
-                            Message::Close(msg) => {
-                                log::debug!("CLOSE received: {msg:X?}");
-                                ws.close(msg).await.unwrap();
-                                break;
-                            },
-                            Message::Frame(_) => unreachable!("raw frames are not exposed here"),
-                        };
-                    }
-
-                    else => {
-                        panic!("else branch reached somehow ?!?");
-                    }
-                };
-            }
+    ) -> Result<WebSocket> {
+        let mut builder = Self::builder();
 
-            log::warn!("WS management task is done");
-        });
-
-        WebSocket {
-            tx: send_tx,
-            rx: recv_rx,
-            keys: public_key
-                .map(ToOwned::to_owned)
-                .zip(private_key.map(ToOwned::to_owned)),
-            challenge: None,
-            signed_challenge: None,
-            _handle: handle,
+        if let (Some(public_key), Some(private_key)) = (public_key, private_key) {
+            builder = builder.credentials(public_key, private_key);
         }
-    }
 
-    pub async fn next_msg(&mut self) -> Option<models::Msg> {
-        self.rx.recv().await
+        builder.connect(ws_url).await
     }
 
-    //// public feeds ////
+    /// Returns a builder for a client with non-default settings.
+    pub fn builder() -> WebSocketBuilder {
+        WebSocketBuilder::new()
+    }
 
-    pub async fn subscribe(&mut self, feed: &str, products: Option<&[&str]>) {
-        let msg = serde_json::to_string(&models::SubscribeMsg {
-            event: "subscribe",
-            feed,
-            product_ids: products,
-            api_key: None,
-            original_challenge: None,
-            signed_challenge: None,
-        })
-        .unwrap();
+    /// Waits for the next message from the server.
+    ///
+    /// Returns [`Error::ChannelClosed`] once the connection has been shut down.
+    pub async fn next_msg(&mut self) -> Result<models::Msg> {
+        self.receiver.next_msg().await
+    }
 
-        info!("subscribe to public feed: {feed}");
+    /// Returns how many messages the [`Backpressure`] policy has discarded or conflated.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.receiver.queue_stats()
+    }
 
-        self.tx.send(Message::Text(msg)).await.unwrap();
+    /// Splits the client into a cloneable [`Commander`], which changes subscriptions and closes
+    /// the connection, and a [`Receiver`], which reads it.
+    ///
+    /// This lets other tasks subscribe and unsubscribe while a read loop keeps running.
+    pub fn split(self) -> (Commander, Receiver) {
+        (self.commander, self.receiver)
     }
 
-    pub async fn unsubscribe(&mut self, feed: &str, products: Option<&[&str]>) {
-        let msg = serde_json::to_string(&models::SubscribeMsg {
-            event: "unsubscribe",
-            feed,
-            product_ids: products,
-            api_key: None,
-            original_challenge: None,
-            signed_challenge: None,
-        })
-        .unwrap();
+    /// Shuts the connection down: optionally unsubscribes from every feed, sends a close frame,
+    /// waits for the server to answer it and stops the connection task.
+    ///
+    /// Returns `true` if the server answered the close frame within [`Close::timeout`].
+    pub async fn close(self, close: Close) -> Result<bool> {
+        let WebSocket {
+            commander,
+            mut receiver,
+            handle,
+        } = self;
+
+        let close = commander.close(close);
+        tokio::pin!(close);
+
+        // Messages are discarded until the task has closed the socket, so that it is neither held
+        // up by a full receive queue nor mistakes a dropped receiver for a shutdown.
+        let mut draining = true;
+
+        let clean = loop {
+            tokio::select! {
+                clean = &mut close => break clean?,
+                msg = receiver.rx.recv(), if draining => draining = msg.is_some(),
+            }
+        };
 
-        info!("unsubscribe from public feed: {feed}");
+        drop(receiver);
+        handle.await.map_err(|_| Error::ChannelClosed)?;
 
-        self.tx.send(Message::Text(msg)).await.unwrap();
+        Ok(clean)
     }
 
-    //// private feeds ////
-
-    pub async fn subscribe_private(&mut self, feed: &str) -> Option<()> {
-        if self.challenge.is_none() {
-            self.sign_challenge().await?;
-        }
-
-        let msg = serde_json::to_string(&models::SubscribeMsg {
-            event: "subscribe",
-            feed,
-            product_ids: None,
-            api_key: self.keys.as_ref().map(|(pb, _)| &**pb),
-            original_challenge: self.challenge.as_deref(),
-            signed_challenge: self.signed_challenge.as_deref(),
-        })
-        .unwrap();
+    /// Returns the current connection state.
+    pub fn state(&self) -> ConnectionState {
+        self.commander.state()
+    }
 
-        info!("subscribe to private feed: {feed}");
+    /// Returns a receiver that is notified of every connection state transition.
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.commander.watch_state()
+    }
 
-        self.tx.send(Message::Text(msg)).await.unwrap();
+    /// Returns a copy of the local order book of `product`.
+    ///
+    /// Books are kept for every product subscribed to on the [`Feed::Book`] feed, once its
+    /// snapshot has been received.
+    pub fn order_book(&self, product: &Product) -> Option<OrderBook> {
+        self.commander.order_book(product)
+    }
 
-        None
+    /// Returns a handle to the local order books that can be shared with other tasks.
+    pub fn order_books(&self) -> OrderBooks {
+        self.commander.order_books()
     }
 
-    pub async fn unsubscribe_private(&mut self, feed: &str) -> Option<()> {
-        if self.challenge.is_none() {
-            self.sign_challenge().await?;
-        }
+    /// Returns the newest tickers of `product`.
+    ///
+    /// Tickers are kept for every product received on the [`Feed::Ticker`] and
+    /// [`Feed::TickerLite`] feeds.
+    pub fn latest_ticker(&self, product: &Product) -> Option<LatestTicker> {
+        self.commander.latest_ticker(product)
+    }
 
-        let msg = serde_json::to_string(&models::SubscribeMsg {
-            event: "subscribe",
-            feed,
-            product_ids: None,
-            api_key: self.keys.as_ref().map(|(pb, _)| &**pb),
-            original_challenge: self.challenge.as_deref(),
-            signed_challenge: self.signed_challenge.as_deref(),
-        })
-        .unwrap();
+    /// Returns a handle to the cache of newest tickers that can be shared with other tasks, to
+    /// watch products or take a snapshot of all of them.
+    pub fn ticker_cache(&self) -> TickerCache {
+        self.commander.ticker_cache()
+    }
 
-        info!("unsubscribe from private feed: {}", feed);
+    /// Returns a new handle that receives every message from now on; see [`Subscriber`].
+    ///
+    /// Any number of subscribers can be created, and each can be moved to its own task. They only
+    /// receive messages if [broadcast mode](WebSocketBuilder::broadcast) is enabled.
+    pub fn subscriber(&self) -> Subscriber {
+        self.commander.subscriber()
+    }
 
-        self.tx.send(Message::Text(msg)).await.unwrap();
+    //// typed streams ////
 
-        None
+    /// Returns a stream of the trades of `product`, including those of trade snapshots.
+    ///
+    /// This only routes messages; subscribe to [`Feed::Trade`] to receive them. See
+    /// [`FeedStream`] for how routed messages are delivered.
+    pub fn trades(&self, product: impl Into<Product>) -> FeedStream<Trade> {
+        self.commander.trades(product)
     }
 
-    // sign challenge request
-    async fn sign_challenge(&mut self) -> Option<()> {
-        match (&self.keys, self.challenge.clone()) {
-            (Some(_), Some(_)) => Some(()),
-            (Some((pb, ref pv)), None) => {
-                let pb = pb.clone();
-                let pv = pv.clone();
-                self.request_challenge(&pb).await;
-                let challenge = self.wait_for_challenge().await;
-                log::debug!("found challenge: {challenge}");
-                self.signed_challenge = Some(Self::sign(&pv, &challenge));
-                self.challenge = Some(challenge);
-                Some(())
-            }
-            _ => None,
-        }
+    /// Returns a stream of the tickers of `product`; see [`WebSocket::trades`].
+    pub fn tickers(&self, product: impl Into<Product>) -> FeedStream<Ticker> {
+        self.commander.tickers(product)
     }
 
-    async fn request_challenge(&mut self, public_key: &str) {
-        let msg = serde_json::to_string(&models::ChallengeMsg {
-            event: "challenge",
-            api_key: public_key,
-        })
-        .unwrap();
+    /// Returns a stream of the book snapshots and deltas of `product`; see
+    /// [`WebSocket::trades`].
+    pub fn book_updates(&self, product: impl Into<Product>) -> FeedStream<BookUpdate> {
+        self.commander.book_updates(product)
+    }
 
-        self.tx.send(Message::Text(msg)).await.unwrap();
+    /// Returns a stream of the account's fills, including those of fill snapshots; see
+    /// [`WebSocket::trades`].
+    pub fn fills(&self) -> FeedStream<Fill> {
+        self.commander.fills()
     }
 
-    // waits until challenge event arrives
-    async fn wait_for_challenge(&mut self) -> String {
-        let mut challenge = String::new();
+    /// Returns a stream of the account's open positions; see [`WebSocket::trades`].
+    pub fn positions(&self) -> FeedStream<OpenPositions> {
+        self.commander.positions()
+    }
 
-        info!("waiting for challenge");
+    /// Returns a stream of the account's balances and margins; see [`WebSocket::trades`].
+    pub fn balances(&self) -> FeedStream<AccountBalancesAndMargins> {
+        self.commander.balances()
+    }
 
-        while let Some(msg) = self.next_msg().await {
-            match msg {
-                models::Msg::Error(c) if c.event == "challenge" => {
-                    challenge = c.message;
-                    break;
-                }
-                _ => {}
-            }
-        }
+    /// Returns a stream of the account's notifications; see [`WebSocket::trades`].
+    pub fn notifications(&self) -> FeedStream<Notification> {
+        self.commander.notifications()
+    }
 
-        challenge
+    //// subscriptions ////
+
+    /// Subscribes to a feed, authenticating first if it is private.
+    ///
+    /// `products` is only sent for feeds that [take product IDs](Feed::takes_products); they
+    /// convert from strings, as in `Some(&["PI_XBTUSD".into()])`. Private feeds fail with
+    /// [`Error::Auth`] if the client was created without API keys.
+    ///
+    /// The request is queued once this returns; await the returned [`Ack`] to wait for the
+    /// server to confirm it.
+    pub async fn subscribe(&mut self, feed: Feed, products: Option<&[Product]>) -> Result<Ack> {
+        self.commander.subscribe(feed, products).await
     }
 
-    fn hmac(secret: &[u8], data: &[u8]) -> Vec<u8> {
-        let mut signer = HmacSha512::new_from_slice(secret).unwrap();
-        signer.update(data);
-        signer.finalize().into_bytes().to_vec()
+    /// Unsubscribes from a feed, or from some of its products.
+    ///
+    /// See [`WebSocket::subscribe`] for the arguments and the returned [`Ack`].
+    pub async fn unsubscribe(&mut self, feed: Feed, products: Option<&[Product]>) -> Result<Ack> {
+        self.commander.unsubscribe(feed, products).await
     }
 
-    fn sign(private_key: &str, challenge: &str) -> String {
-        let challenge_hash = Sha256::digest(challenge);
-        let secret = BASE64_STANDARD.decode(private_key).unwrap();
-        let digest = Self::hmac(&secret, &challenge_hash);
-        BASE64_STANDARD.encode(digest)
+    /// Returns the private feeds the server has confirmed on the current connection.
+    ///
+    /// The set is emptied when the connection drops and refilled as the feeds are restored.
+    pub fn private_subscriptions(&self) -> BTreeSet<Feed> {
+        self.commander.private_subscriptions()
     }
 }
 
-impl Drop for WebSocket {
-    fn drop(&mut self) {
-        drop(self.tx.send(Message::Close(None)));
+impl Stream for WebSocket {
+    type Item = Result<models::Msg>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
     }
 }
@@ -1,4 +1,269 @@
-use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, fmt, str::FromStr, sync::Arc};
+
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::{BookInvalidated, ConnectionState, Error, Product};
+
+/// Prices and other monetary amounts and rates.
+///
+/// `f64` by default; with the `decimal` feature, an exact [`rust_decimal::Decimal`] parsed
+/// directly from the number text of the message.
+#[cfg(not(feature = "decimal"))]
+pub type Price = f64;
+
+/// Prices and other monetary amounts and rates, as exact decimals.
+#[cfg(feature = "decimal")]
+pub type Price = rust_decimal::Decimal;
+
+/// Quantities, sizes and volumes; see [`Price`].
+pub type Qty = Price;
+
+/// Point in time sent as milliseconds since the Unix epoch.
+///
+/// `u64` milliseconds by default; with the `chrono` feature, a [`chrono::DateTime`] in UTC.
+#[cfg(not(feature = "chrono"))]
+pub type Timestamp = u64;
+
+/// Point in time sent as milliseconds since the Unix epoch, in UTC.
+#[cfg(feature = "chrono")]
+pub type Timestamp = chrono::DateTime<chrono::Utc>;
+
+/// Point in time sent as an ISO 8601 string.
+///
+/// The string as sent by default; with the `chrono` feature, a [`chrono::DateTime`] in UTC.
+#[cfg(not(feature = "chrono"))]
+pub type IsoTimestamp = String;
+
+/// Point in time sent as an ISO 8601 string, in UTC.
+#[cfg(feature = "chrono")]
+pub type IsoTimestamp = chrono::DateTime<chrono::Utc>;
+
+// deserializers for times sent as milliseconds since the Unix epoch
+mod millis {
+    use serde::{de, Deserialize, Deserializer};
+
+    use super::Timestamp;
+
+    // some feeds send whole milliseconds as floats
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Timestamp, D::Error> {
+        let number = serde_json::Number::deserialize(deserializer)?;
+
+        let millis = number
+            .as_u64()
+            .or_else(|| number.as_f64().filter(|ms| *ms >= 0.0).map(|ms| ms as u64))
+            .ok_or_else(|| de::Error::custom(format!("invalid timestamp `{number}`")))?;
+
+        from_millis(millis)
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    fn from_millis<E: de::Error>(millis: u64) -> Result<Timestamp, E> {
+        Ok(millis)
+    }
+
+    #[cfg(feature = "chrono")]
+    fn from_millis<E: de::Error>(millis: u64) -> Result<Timestamp, E> {
+        i64::try_from(millis)
+            .ok()
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .ok_or_else(|| E::custom(format!("timestamp out of range: {millis}")))
+    }
+
+    pub(super) mod option {
+        use super::*;
+
+        pub(in crate::models) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Timestamp>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(deserialize_with = "super::deserialize")] Timestamp);
+
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(ts)| ts))
+        }
+    }
+
+    // maturity is 0 for perpetual contracts
+    pub(super) mod maturity {
+        use super::*;
+
+        pub(in crate::models) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Timestamp>, D::Error> {
+            let number = serde_json::Number::deserialize(deserializer)?;
+
+            if number.as_u64() == Some(0) {
+                return Ok(None);
+            }
+
+            super::deserialize(number)
+                .map(Some)
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+// Enum of the string values of a field. Values are matched ignoring ASCII case, and ones this
+// crate does not know yet decode to `Other` instead of failing the whole message.
+macro_rules! string_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $value:literal $(| $alias:literal)*,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+
+            /// A value not known to this version of the crate.
+            Other(String),
+        }
+
+        impl $name {
+            /// Returns the value as sent by the server.
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $value,)*
+                    Self::Other(value) => value,
+                }
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                $(
+                    if value.eq_ignore_ascii_case($value) $(|| value.eq_ignore_ascii_case($alias))* {
+                        return Self::$variant;
+                    }
+                )*
+
+                Self::Other(value)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                Self::from(value.to_owned())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer).map(Self::from)
+            }
+        }
+    };
+}
+
+string_enum! {
+    /// Side of a trade or of the book.
+    pub enum Side {
+        Buy = "buy",
+        Sell = "sell",
+    }
+}
+
+string_enum! {
+    /// How a trade came about.
+    pub enum TradeType {
+        Fill = "fill",
+        Liquidation = "liquidation",
+        Termination = "termination",
+        Block = "block",
+    }
+}
+
+string_enum! {
+    /// Order type of an open order or a fill.
+    pub enum OrderType {
+        Limit = "lmt" | "limit",
+        PostOnly = "post",
+        ImmediateOrCancel = "ioc",
+        Market = "mkt" | "market",
+        Stop = "stp" | "stop",
+        TakeProfit = "take_profit" | "takeprofit",
+        Liquidation = "liquidation",
+    }
+}
+
+string_enum! {
+    /// Role of the account in a fill.
+    pub enum FillType {
+        Maker = "maker",
+        Taker = "taker",
+        Liquidation = "liquidation",
+        Assignee = "assignee",
+        Assignor = "assignor",
+        TakerAfterEdit = "takerAfterEdit",
+        UnwindBankrupt = "unwindBankrupt",
+        UnwindCounterparty = "unwindCounterparty",
+    }
+}
+
+string_enum! {
+    /// Why an open orders update was sent.
+    pub enum OrderUpdateReason {
+        NewPlacedOrderByUser = "new_placed_order_by_user",
+        Liquidation = "liquidation",
+        StopOrderTriggered = "stop_order_triggered",
+        LimitOrderFromStop = "limit_order_from_stop",
+        PartialFill = "partial_fill",
+        FullFill = "full_fill",
+        CancelledByUser = "cancelled_by_user",
+        ContractExpired = "contract_expired",
+        NotEnoughMargin = "not_enough_margin",
+        MarketInactive = "market_inactive",
+        CancelledByAdmin = "cancelled_by_admin",
+        DeadManSwitch = "dead_man_switch",
+        IocOrderFailed = "ioc_order_failed_because_it_would_not_be_executed",
+        PostOrderFailed = "post_order_failed_because_it_would_filled",
+        WouldExecuteSelf = "would_execute_self",
+        WouldNotReducePosition = "would_not_reduce_position",
+        OrderForEditNotFound = "order_for_edit_not_found",
+    }
+}
+
+string_enum! {
+    /// Status of a deposit or withdrawal.
+    pub enum TransferStatus {
+        Pending = "pending",
+        Confirmed = "confirmed",
+        Completed = "completed",
+        Failed = "failed",
+        Cancelled = "cancelled",
+    }
+}
+
+string_enum! {
+    /// Category of a notification.
+    pub enum NotificationType {
+        General = "general",
+        NewFeature = "new_feature",
+        BugFix = "bug_fix",
+        Maintenance = "maintenance",
+        Settlement = "settlement",
+        Market = "market",
+    }
+}
+
+string_enum! {
+    /// Urgency of a notification.
+    pub enum NotificationPriority {
+        Low = "low",
+        Medium = "medium",
+        High = "high",
+    }
+}
 
 #[derive(Debug, Serialize)]
 pub(crate) struct SubscribeMsg<'a> {
@@ -20,167 +285,182 @@ pub(crate) struct ChallengeMsg<'a> {
     pub(crate) api_key: &'a str,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Header {
     pub feed: String,
     #[serde(default)]
-    pub product_ids: Option<Vec<String>>,
+    pub product_ids: Option<Vec<Product>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Trade {
     #[serde(flatten)]
     pub header: Header,
     #[serde(default)]
-    pub product_id: Option<String>,
-    pub side: String,
+    pub product_id: Option<Product>,
+    pub side: Side,
     #[serde(rename = "type")]
-    pub ty: String,
+    pub ty: TradeType,
     pub seq: u64,
-    pub time: u64,
-    pub qty: f64,
-    pub price: f64,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub time: Timestamp,
+    pub qty: Qty,
+    pub price: Price,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct TradeSnapshot {
     #[serde(flatten)]
     pub header: Header,
     pub trades: Vec<Trade>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct BookValue {
     #[serde(default)]
     pub feed: Option<String>,
     #[serde(default)]
-    pub product_id: Option<String>,
+    pub product_id: Option<Product>,
     #[serde(default)]
-    pub side: Option<String>,
+    pub side: Option<Side>,
     #[serde(default)]
     pub seq: Option<u64>,
-    pub price: f64,
-    pub qty: f64,
+    pub price: Price,
+    pub qty: Qty,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct BookSnapshot {
     pub feed: String,
-    pub product_id: String,
+    pub product_id: Product,
     pub seq: u64,
-    pub timestamp: u64,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub timestamp: Timestamp,
     pub bids: Vec<BookValue>,
     pub asks: Vec<BookValue>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct TickerLite {
     pub feed: String,
-    pub product_id: String,
-    pub bid: f64,
-    pub ask: f64,
-    pub change: f64,
-    pub premium: f64,
-    pub volume: f64,
+    pub product_id: Product,
+    pub bid: Price,
+    pub ask: Price,
+    pub change: Price,
+    pub premium: Price,
+    /// Volume in base units.
+    pub volume: Qty,
     pub tag: String,
     pub pair: String,
     pub dtm: i64,
-    #[serde(rename = "maturityTime")]
-    pub maturity_time: u64,
+    /// `None` for perpetuals.
+    #[serde(
+        rename = "maturityTime",
+        deserialize_with = "millis::maturity::deserialize"
+    )]
+    pub maturity_time: Option<Timestamp>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Ticker {
     #[serde(flatten)]
     pub ticker_lite: TickerLite,
-    pub bid_size: f64,
-    pub ask_size: f64,
+    pub bid_size: Qty,
+    pub ask_size: Qty,
     pub leverage: String,
-    pub index: f64,
-    pub last: f64,
-    pub time: f64,
+    pub index: Price,
+    pub last: Price,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub time: Timestamp,
     pub post_only: bool,
     pub suspended: bool,
 
-    /// Volume in base units.
-    pub volume: f64,
-
     /// Volume in quote units.
     #[serde(rename = "volumeQuote")]
-    pub volume_quote: f64,
+    pub volume_quote: Price,
 
     #[serde(rename = "openInterest")]
-    pub open_interest: f64,
+    pub open_interest: Qty,
 
     #[serde(rename = "markPrice")]
-    pub mark_price: f64,
-
-    /// Timestamp in milliseconds, or 0 for perpetuals.
-    #[serde(rename = "maturityTime")]
-    pub maturity_time: u64,
+    pub mark_price: Price,
 
     #[serde(default)]
-    pub funding_rate: Option<f64>,
+    pub funding_rate: Option<Price>,
 
     #[serde(default)]
-    pub relative_funding_rate: Option<f64>,
+    pub relative_funding_rate: Option<Price>,
 
-    /// Timestamp in milliseconds.
-    #[serde(default)]
-    pub next_funding_rate_time: Option<u64>,
+    #[serde(default, deserialize_with = "millis::option::deserialize")]
+    pub next_funding_rate_time: Option<Timestamp>,
 
     #[serde(default)]
-    pub funding_rate_prediction: Option<f64>,
+    pub funding_rate_prediction: Option<Price>,
 
     #[serde(default)]
-    pub relative_funding_rate_prediction: Option<f64>,
+    pub relative_funding_rate_prediction: Option<Price>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Heartbeat {
     #[serde(flatten)]
     pub header: Header,
-    pub time: u64,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub time: Timestamp,
 }
 
-#[derive(Debug, Deserialize)]
+/// Challenge to sign before subscribing to private feeds.
+#[derive(Debug, Clone, Deserialize)]
 pub struct Challenge {
-    pub feed: String,
-    pub event: String,
     pub message: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Acknowledgement of a subscribe or unsubscribe request.
+#[derive(Debug, Clone, Deserialize)]
 pub struct Subscribed {
-    pub event: String,
     #[serde(flatten)]
     pub header: Header,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Error {
-    pub event: String,
+/// Rejection of a subscribe or unsubscribe request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscriptionFailed {
+    #[serde(default)]
+    pub feed: Option<String>,
+    #[serde(default)]
+    pub product_ids: Option<Vec<Product>>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ErrorEvent {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Alert {
     pub message: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Version {
-    pub event: String,
+/// API version, sent by the server when the connection opens.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Info {
     pub version: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MarginAccount {
     pub name: String,
-    pub balance: f64,
-    pub pnl: f64,
-    pub pv: f64,
-    pub am: f64,
-    pub im: f64,
-    pub mm: f64,
+    pub balance: Price,
+    pub pnl: Price,
+    pub pv: Price,
+    pub am: Price,
+    pub im: Price,
+    pub mm: Price,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct AccountBalancesAndMargins {
     pub feed: String,
     pub account: String,
@@ -188,131 +468,133 @@ pub struct AccountBalancesAndMargins {
     pub margin_accounts: Vec<MarginAccount>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Log {
     pub id: u64,
-    pub date: String,
+    pub date: IsoTimestamp,
     pub asset: String,
     pub info: String,
     pub booking_uid: String,
     pub margin_account: String,
-    pub old_balance: f64,
-    pub new_balance: f64,
-    pub old_average_entry_price: f64,
-    pub new_average_entry_price: f64,
-    pub trade_price: f64,
-    pub mark_price: f64,
-    pub fee: f64,
+    pub old_balance: Price,
+    pub new_balance: Price,
+    pub old_average_entry_price: Price,
+    pub new_average_entry_price: Price,
+    pub trade_price: Price,
+    pub mark_price: Price,
+    pub fee: Price,
     pub execution: String,
     pub collateral: String,
-    pub funding_rate: f64,
-    pub realised_funding: f64,
+    pub funding_rate: Price,
+    pub realised_funding: Price,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct AccountLog {
     pub feed: String,
     pub logs: Vec<Log>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DepositWithdrawal {
     pub uid: String,
-    pub time: String,
-    pub amount: f64,
+    pub time: IsoTimestamp,
+    pub amount: Qty,
     pub unit: String,
     pub receiving_address: String,
-    pub status: String,
+    pub status: TransferStatus,
     pub confirmations: u64,
     pub tx_reference: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DepositsWithdrawals {
     pub feed: String,
     pub elements: Vec<DepositWithdrawal>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Fill {
-    pub instrument: String,
-    pub time: u64,
-    pub price: f64,
+    pub instrument: Product,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub time: Timestamp,
+    pub price: Price,
     pub seq: u64,
     pub buy: bool,
-    pub qty: f64,
+    pub qty: Qty,
     pub order_id: String,
     pub fill_id: String,
-    pub fill_type: String,
+    pub fill_type: FillType,
     pub fee_currency: String,
-    pub fee_paid: f64,
-    pub order_type: String,
-    pub remaining_order_qty: f64,
-    pub taker_order_type: String,
+    pub fee_paid: Price,
+    pub order_type: OrderType,
+    pub remaining_order_qty: Qty,
+    pub taker_order_type: OrderType,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FillsSnapshot {
     pub feed: String,
     pub account: String,
     pub fills: Vec<Fill>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Fills {
     pub feed: String,
     pub username: String,
     pub fills: Vec<Fill>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Position {
-    pub instrument: String,
-    pub balance: f64,
-    pub pnl: f64,
-    pub entry_price: f64,
-    pub mark_price: f64,
-    pub index_price: f64,
-    pub liquidation_threshold: f64,
-    pub effective_leverage: f64,
-    pub return_on_equity: f64,
-}
-
-#[derive(Debug, Deserialize)]
+    pub instrument: Product,
+    pub balance: Qty,
+    pub pnl: Price,
+    pub entry_price: Price,
+    pub mark_price: Price,
+    pub index_price: Price,
+    pub liquidation_threshold: Price,
+    pub effective_leverage: Price,
+    pub return_on_equity: Price,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct OpenPositions {
     pub feed: String,
     pub account: String,
     pub positions: Vec<Position>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Order {
-    pub instrument: String,
-    pub time: u64,
-    pub qty: f64,
-    pub filled: f64,
-    pub limit_price: f64,
-    pub stop_price: f64,
+    pub instrument: Product,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub time: Timestamp,
+    pub qty: Qty,
+    pub filled: Qty,
+    pub limit_price: Price,
+    pub stop_price: Price,
     #[serde(rename = "type")]
-    pub ty: String,
+    pub ty: OrderType,
     pub order_id: String,
     #[serde(default)]
     pub cli_order_id: Option<String>,
     pub direction: i64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct OpenOrdersSnapshot {
     pub feed: String,
     pub account: String,
     pub orders: Vec<Order>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct OpenOrders {
     pub feed: String,
     pub is_cancel: bool,
-    pub reason: String,
+    pub reason: OrderUpdateReason,
     #[serde(default)]
     pub order_id: Option<String>,
     #[serde(default)]
@@ -321,28 +603,33 @@ pub struct OpenOrders {
     pub order: Option<Order>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Notification {
     pub id: u64,
     #[serde(rename = "type")]
-    pub ty: String,
-    pub priority: String,
+    pub ty: NotificationType,
+    pub priority: NotificationPriority,
     pub note: String,
-    pub effective_time: u64,
+    #[serde(deserialize_with = "millis::deserialize")]
+    pub effective_time: Timestamp,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Notifications {
     pub feed: String,
     pub notifications: Vec<Notification>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug, Clone)]
 pub enum Msg {
-    Version(Version),
+    Info(Info),
+    Challenge(Challenge),
+    Alert(Alert),
     Subscribed(Subscribed),
-    Error(Error),
+    SubscribedFailed(SubscriptionFailed),
+    Unsubscribed(Subscribed),
+    UnsubscribedFailed(SubscriptionFailed),
+    Error(ErrorEvent),
     Trade(Trade),
     TradeSnapshot(TradeSnapshot),
     Book(BookValue),
@@ -350,8 +637,6 @@ pub enum Msg {
     Ticker(Ticker),
     TickerLite(TickerLite),
     Heartbeat(Heartbeat),
-    // TODO: challenge is not reachable
-    Challenge(Challenge),
     AccountBalancesAndMargins(AccountBalancesAndMargins),
     AccountLog(AccountLog),
     DepositsWithdrawals(DepositsWithdrawals),
@@ -361,4 +646,307 @@ pub enum Msg {
     OpenOrders(OpenOrders),
     OpenOrdersSnapshot(OpenOrdersSnapshot),
     Notifications(Notifications),
+
+    /// Message with an `event` or `feed` this crate does not know about.
+    Unknown(Value),
+
+    /// Connection lifecycle transition; generated locally, never sent by the server.
+    ConnectionState(ConnectionState),
+
+    /// A local order book missed deltas and is being resynced; generated locally.
+    BookInvalidated(BookInvalidated),
+
+    /// This many messages were discarded by the [`Backpressure`](crate::Backpressure) policy
+    /// since the previous report; generated locally.
+    Dropped(u64),
+
+    /// A failure that [`WebSocket::next_msg`](crate::WebSocket::next_msg) would return as an
+    /// error, delivered to subscribers in
+    /// [broadcast mode](crate::WebSocketBuilder::broadcast) instead; generated locally.
+    Failure(Arc<Error>),
+}
+
+impl<'de> Deserialize<'de> for Msg {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+
+        let tag = Tag {
+            feed: value
+                .get("feed")
+                .and_then(Value::as_str)
+                .map(|feed| feed.to_owned().into()),
+            event: value
+                .get("event")
+                .and_then(Value::as_str)
+                .map(|event| event.to_owned().into()),
+        };
+
+        dispatch(&tag, value).map_err(de::Error::custom)
+    }
+}
+
+/// Decodes a text frame, reading its `event` and `feed` keys before decoding the matching model.
+impl FromStr for Msg {
+    type Err = serde_json::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let tag = serde_json::from_str::<Tag<'_>>(text)?;
+        dispatch(&tag, text)
+    }
+}
+
+// keys identifying the kind of a message
+#[derive(Deserialize)]
+struct Tag<'a> {
+    #[serde(borrow, default)]
+    feed: Option<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    event: Option<Cow<'a, str>>,
+}
+
+// input a single model can be decoded from
+trait Source {
+    fn decode<T: DeserializeOwned>(self) -> serde_json::Result<T>;
+}
+
+impl Source for Value {
+    fn decode<T: DeserializeOwned>(self) -> serde_json::Result<T> {
+        T::deserialize(self)
+    }
+}
+
+impl Source for &str {
+    fn decode<T: DeserializeOwned>(self) -> serde_json::Result<T> {
+        serde_json::from_str(self)
+    }
+}
+
+fn dispatch(tag: &Tag<'_>, src: impl Source) -> serde_json::Result<Msg> {
+    let msg = match (tag.event.as_deref(), tag.feed.as_deref()) {
+        (Some("info"), _) => Msg::Info(src.decode()?),
+        (Some("challenge"), _) => Msg::Challenge(src.decode()?),
+        (Some("alert"), _) => Msg::Alert(src.decode()?),
+        (Some("subscribed"), _) => Msg::Subscribed(src.decode()?),
+        (Some("subscribed_failed"), _) => Msg::SubscribedFailed(src.decode()?),
+        (Some("unsubscribed"), _) => Msg::Unsubscribed(src.decode()?),
+        (Some("unsubscribed_failed"), _) => Msg::UnsubscribedFailed(src.decode()?),
+        (Some("error"), _) => Msg::Error(src.decode()?),
+        (Some(_), _) => Msg::Unknown(src.decode()?),
+
+        (None, Some("trade")) => Msg::Trade(src.decode()?),
+        (None, Some("trade_snapshot")) => Msg::TradeSnapshot(src.decode()?),
+        (None, Some("book")) => Msg::Book(src.decode()?),
+        (None, Some("book_snapshot")) => Msg::BookSnapshot(src.decode()?),
+        (None, Some("ticker")) => Msg::Ticker(src.decode()?),
+        (None, Some("ticker_lite")) => Msg::TickerLite(src.decode()?),
+        (None, Some("heartbeat")) => Msg::Heartbeat(src.decode()?),
+        (None, Some("account_balances_and_margins")) => {
+            Msg::AccountBalancesAndMargins(src.decode()?)
+        }
+        (None, Some("account_log" | "account_log_snapshot")) => Msg::AccountLog(src.decode()?),
+        (None, Some("deposits_withdrawals")) => Msg::DepositsWithdrawals(src.decode()?),
+        (None, Some("fills_snapshot")) => Msg::FillsSnapshot(src.decode()?),
+        (None, Some("fills")) => Msg::Fills(src.decode()?),
+        (None, Some("open_positions")) => Msg::OpenPositions(src.decode()?),
+        (None, Some("open_orders" | "open_orders_verbose")) => Msg::OpenOrders(src.decode()?),
+        (None, Some("open_orders_snapshot" | "open_orders_verbose_snapshot")) => {
+            Msg::OpenOrdersSnapshot(src.decode()?)
+        }
+        (None, Some("notifications_auth")) => Msg::Notifications(src.decode()?),
+
+        (None, _) => Msg::Unknown(src.decode()?),
+    };
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKER_LITE: &str = r#""product_id": "PI_XBTUSD", "bid": 100, "ask": 101, "change": 1,
+        "premium": 0, "volume": 10, "tag": "perpetual", "pair": "XBT:USD", "dtm": 0,
+        "maturityTime": 0"#;
+
+    const TICKER: &str = r#""bid_size": 1, "ask_size": 2, "leverage": "50x", "index": 100,
+        "last": 100, "time": 1612269825817, "post_only": false, "suspended": false,
+        "volumeQuote": 1000, "openInterest": 5, "markPrice": 100"#;
+
+    // a message of every kind, with the variant it decodes to
+    fn samples() -> Vec<(String, &'static str)> {
+        let mut samples = vec![
+            (r#"{"event": "info", "version": 1}"#.to_owned(), "Info"),
+            (
+                r#"{"event": "challenge", "message": "c"}"#.to_owned(),
+                "Challenge",
+            ),
+            (r#"{"event": "alert", "message": "m"}"#.to_owned(), "Alert"),
+            (r#"{"event": "error", "message": "m"}"#.to_owned(), "Error"),
+            (r#"{"event": "pong"}"#.to_owned(), "Unknown"),
+            (
+                r#"{"feed": "new_feed", "product_id": "PI_XBTUSD"}"#.to_owned(),
+                "Unknown",
+            ),
+            (r#"{"version": 1}"#.to_owned(), "Unknown"),
+            (
+                r#"{"feed": "trade", "product_id": "PI_XBTUSD", "side": "buy", "type": "fill",
+                    "seq": 1, "time": 1612269825817, "qty": 1, "price": 100}"#
+                    .to_owned(),
+                "Trade",
+            ),
+            (
+                r#"{"feed": "book", "product_id": "PI_XBTUSD", "side": "sell", "seq": 2,
+                    "price": 101, "qty": 3, "timestamp": 1612269825817}"#
+                    .to_owned(),
+                "Book",
+            ),
+            (
+                r#"{"feed": "book_snapshot", "product_id": "PI_XBTUSD", "seq": 1,
+                    "timestamp": 1612269825817, "bids": [], "asks": []}"#
+                    .to_owned(),
+                "BookSnapshot",
+            ),
+            (
+                format!(r#"{{"feed": "ticker", {TICKER_LITE}, {TICKER}}}"#),
+                "Ticker",
+            ),
+            (
+                format!(r#"{{"feed": "ticker_lite", {TICKER_LITE}}}"#),
+                "TickerLite",
+            ),
+            (
+                r#"{"feed": "heartbeat", "time": 1612269825817}"#.to_owned(),
+                "Heartbeat",
+            ),
+            (
+                r#"{"feed": "account_balances_and_margins", "account": "a", "seq": 1,
+                    "margin_accounts": []}"#
+                    .to_owned(),
+                "AccountBalancesAndMargins",
+            ),
+            (
+                r#"{"feed": "deposits_withdrawals", "elements": []}"#.to_owned(),
+                "DepositsWithdrawals",
+            ),
+            (
+                r#"{"feed": "fills_snapshot", "account": "a", "fills": []}"#.to_owned(),
+                "FillsSnapshot",
+            ),
+            (
+                r#"{"feed": "fills", "username": "u", "fills": []}"#.to_owned(),
+                "Fills",
+            ),
+            (
+                r#"{"feed": "open_positions", "account": "a", "positions": []}"#.to_owned(),
+                "OpenPositions",
+            ),
+            (
+                r#"{"feed": "notifications_auth", "notifications": []}"#.to_owned(),
+                "Notifications",
+            ),
+        ];
+
+        for (event, variant) in [
+            ("subscribed", "Subscribed"),
+            ("unsubscribed", "Unsubscribed"),
+            ("subscribed_failed", "SubscribedFailed"),
+            ("unsubscribed_failed", "UnsubscribedFailed"),
+        ] {
+            samples.push((
+                format!(r#"{{"event": "{event}", "feed": "trade", "product_ids": ["PI_XBTUSD"]}}"#),
+                variant,
+            ));
+        }
+
+        samples.push((
+            r#"{"feed": "trade_snapshot", "product_id": "PI_XBTUSD", "trades": []}"#.to_owned(),
+            "TradeSnapshot",
+        ));
+
+        for feed in ["account_log", "account_log_snapshot"] {
+            samples.push((format!(r#"{{"feed": "{feed}", "logs": []}}"#), "AccountLog"));
+        }
+
+        for feed in ["open_orders", "open_orders_verbose"] {
+            samples.push((
+                format!(r#"{{"feed": "{feed}", "is_cancel": false, "reason": "full_fill"}}"#),
+                "OpenOrders",
+            ));
+        }
+
+        for feed in ["open_orders_snapshot", "open_orders_verbose_snapshot"] {
+            samples.push((
+                format!(r#"{{"feed": "{feed}", "account": "a", "orders": []}}"#),
+                "OpenOrdersSnapshot",
+            ));
+        }
+
+        samples
+    }
+
+    fn variant(msg: &Msg) -> String {
+        let debug = format!("{msg:?}");
+        debug[..debug.find('(').unwrap_or(debug.len())].to_owned()
+    }
+
+    #[test]
+    fn names_map_to_variants() {
+        for (json, expected) in samples() {
+            let msg = json
+                .parse::<Msg>()
+                .unwrap_or_else(|err| panic!("{json}: {err}"));
+            assert_eq!(variant(&msg), expected, "{json}");
+        }
+    }
+
+    #[test]
+    fn deserialize_and_from_str_agree() {
+        for (json, _) in samples() {
+            let parsed = json.parse::<Msg>().unwrap();
+            let deserialized = serde_json::from_str::<Msg>(&json).unwrap();
+            assert_eq!(format!("{parsed:?}"), format!("{deserialized:?}"), "{json}");
+        }
+    }
+
+    #[test]
+    fn ticker_with_book_fields_is_a_ticker() {
+        // the fields of a book delta must not make a ticker look like one
+        let json = format!(
+            r#"{{"feed": "ticker", "side": "buy", "seq": 1, "price": 100, "qty": 1,
+                {TICKER_LITE}, {TICKER}}}"#
+        );
+
+        assert_eq!(variant(&json.parse().unwrap()), "Ticker");
+        assert_eq!(variant(&serde_json::from_str(&json).unwrap()), "Ticker");
+    }
+
+    #[test]
+    fn unknown_string_values_are_kept() {
+        let json = r#"{"feed": "trade", "product_id": "PI_XBTUSD", "side": "BUY",
+            "type": "auction", "seq": 1, "time": 1612269825817, "qty": 1, "price": 100}"#;
+
+        let Ok(Msg::Trade(trade)) = json.parse() else {
+            panic!("not a trade");
+        };
+        assert_eq!(trade.side, Side::Buy);
+        assert_eq!(trade.ty, TradeType::Other("auction".to_owned()));
+        assert_eq!(trade.ty.as_str(), "auction");
+        assert_eq!(OrderType::from("lmt".to_owned()), OrderType::Limit);
+        assert_eq!(OrderType::from("Limit".to_owned()), OrderType::Limit);
+    }
+
+    #[test]
+    fn zero_maturity_is_none() {
+        let perpetual = format!(r#"{{"feed": "ticker_lite", {TICKER_LITE}}}"#);
+        let Ok(Msg::TickerLite(ticker)) = perpetual.parse() else {
+            panic!("not a ticker");
+        };
+        assert!(ticker.maturity_time.is_none());
+
+        let future = perpetual.replace(r#""maturityTime": 0"#, r#""maturityTime": 1680220800000"#);
+        let Ok(Msg::TickerLite(ticker)) = future.parse() else {
+            panic!("not a ticker");
+        };
+        assert!(ticker.maturity_time.is_some());
+    }
 }
@@ -0,0 +1,301 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer};
+
+/// Kind of instrument, as given by the prefix of its symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ProductKind {
+    /// `PI_`: inverse perpetual, margined in the base currency.
+    InversePerpetual,
+    /// `PF_`: linear perpetual, margined in the quote currency.
+    LinearPerpetual,
+    /// `FI_`: inverse future with a fixed maturity.
+    InverseFuture,
+    /// `FF_`: linear future with a fixed maturity.
+    LinearFuture,
+    /// `IN_`: real-time index.
+    Index,
+    /// `RR_`: reference rate.
+    ReferenceRate,
+    /// A prefix not known to this version of the crate.
+    Unknown,
+}
+
+impl ProductKind {
+    fn from_prefix(prefix: &str) -> Self {
+        match prefix {
+            "PI" => ProductKind::InversePerpetual,
+            "PF" => ProductKind::LinearPerpetual,
+            "FI" => ProductKind::InverseFuture,
+            "FF" => ProductKind::LinearFuture,
+            "IN" => ProductKind::Index,
+            "RR" => ProductKind::ReferenceRate,
+            _ => ProductKind::Unknown,
+        }
+    }
+
+    /// Returns true for futures with a fixed maturity.
+    pub fn has_expiry(self) -> bool {
+        matches!(self, ProductKind::InverseFuture | ProductKind::LinearFuture)
+    }
+}
+
+/// Maturity date of a fixed-maturity future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Expiry {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Expiry {
+    // `YYMMDD`, as used in symbols
+    fn parse(s: &str) -> Option<Self> {
+        if s.len() != 6 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let expiry = Self {
+            year: 2000 + s[0..2].parse::<u16>().ok()?,
+            month: s[2..4].parse().ok()?,
+            day: s[4..6].parse().ok()?,
+        };
+
+        ((1..=12).contains(&expiry.month) && (1..=31).contains(&expiry.day)).then_some(expiry)
+    }
+
+    /// Returns the expiry as a calendar date, if it is a valid one.
+    #[cfg(feature = "chrono")]
+    pub fn date(self) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::from_ymd_opt(self.year.into(), self.month.into(), self.day.into())
+    }
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+// quote currencies, longest first so that `USDT` is not read as `USD`
+const QUOTES: [&str; 7] = ["USDT", "USDC", "USD", "EUR", "GBP", "XBT", "ETH"];
+
+/// A product ID such as `PI_XBTUSD`, `PF_ETHUSD` or `FI_XBTUSD_230331`.
+///
+/// Symbols are stored upper-case, so the same instrument compares equal whether it comes from a
+/// public feed (`PI_XBTUSD`) or a private one (`pi_xbtusd`).
+///
+/// Converting from a string never fails: parts of the symbol that cannot be recognised are
+/// reported as [`ProductKind::Unknown`] or `None`. Use [`str::parse`] to reject such symbols.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Product {
+    symbol: String,
+    kind: ProductKind,
+    // byte offsets of the start of the base, the start of the quote and the end of the pair
+    pair: Option<[usize; 3]>,
+    expiry: Option<Expiry>,
+}
+
+impl Product {
+    /// The full symbol, upper-case.
+    pub fn as_str(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Kind of instrument, from the prefix of the symbol.
+    pub fn kind(&self) -> ProductKind {
+        self.kind
+    }
+
+    /// Base currency, e.g. `XBT` for `PI_XBTUSD`.
+    pub fn base(&self) -> Option<&str> {
+        self.pair.map(|[base, quote, _]| &self.symbol[base..quote])
+    }
+
+    /// Quote currency, e.g. `USD` for `PI_XBTUSD`.
+    pub fn quote(&self) -> Option<&str> {
+        self.pair.map(|[_, quote, end]| &self.symbol[quote..end])
+    }
+
+    /// Maturity of fixed-maturity futures.
+    pub fn expiry(&self) -> Option<Expiry> {
+        self.expiry
+    }
+
+    /// Returns true if the symbol follows a known format in full.
+    pub fn is_well_formed(&self) -> bool {
+        self.kind != ProductKind::Unknown
+            && self.pair.is_some()
+            && self.kind.has_expiry() == self.expiry.is_some()
+            && self.symbol.split('_').count() == 2 + usize::from(self.kind.has_expiry())
+    }
+}
+
+impl From<String> for Product {
+    fn from(mut symbol: String) -> Self {
+        symbol.make_ascii_uppercase();
+
+        let (prefix, rest) = symbol.split_once('_').unwrap_or((&symbol, ""));
+        let (pair, suffix) = rest.split_once('_').unwrap_or((rest, ""));
+        let kind = ProductKind::from_prefix(prefix);
+
+        let pair = QUOTES
+            .iter()
+            .find(|quote| pair.len() > quote.len() && pair.ends_with(*quote))
+            .map(|quote| {
+                let base = prefix.len() + 1;
+                let end = base + pair.len();
+                [base, end - quote.len(), end]
+            });
+
+        let expiry = Some(suffix)
+            .filter(|_| kind.has_expiry())
+            .and_then(Expiry::parse);
+
+        Self {
+            symbol,
+            kind,
+            pair,
+            expiry,
+        }
+    }
+}
+
+impl From<&str> for Product {
+    fn from(symbol: &str) -> Self {
+        Self::from(symbol.to_owned())
+    }
+}
+
+impl AsRef<str> for Product {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Product {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.symbol)
+    }
+}
+
+/// Error returned when parsing a symbol that does not follow a known format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidProduct(pub String);
+
+impl fmt::Display for InvalidProduct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid product ID `{}`", self.0)
+    }
+}
+
+impl std::error::Error for InvalidProduct {}
+
+impl FromStr for Product {
+    type Err = InvalidProduct;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let product = Self::from(s);
+
+        if product.is_well_formed() {
+            Ok(product)
+        } else {
+            Err(InvalidProduct(s.to_owned()))
+        }
+    }
+}
+
+// lenient, so that new instrument types do not break decoding
+impl<'de> Deserialize<'de> for Product {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(symbol: &str) -> Product {
+        symbol.parse().unwrap()
+    }
+
+    #[test]
+    fn perpetuals() {
+        let inverse = parse("PI_XBTUSD");
+        assert_eq!(inverse.kind(), ProductKind::InversePerpetual);
+        assert_eq!(
+            (inverse.base(), inverse.quote()),
+            (Some("XBT"), Some("USD"))
+        );
+        assert_eq!(inverse.expiry(), None);
+
+        let linear = parse("PF_ETHUSD");
+        assert_eq!(linear.kind(), ProductKind::LinearPerpetual);
+        assert_eq!((linear.base(), linear.quote()), (Some("ETH"), Some("USD")));
+    }
+
+    #[test]
+    fn futures() {
+        let inverse = parse("FI_XBTUSD_230331");
+        assert_eq!(inverse.kind(), ProductKind::InverseFuture);
+        assert_eq!(
+            (inverse.base(), inverse.quote()),
+            (Some("XBT"), Some("USD"))
+        );
+        assert_eq!(
+            inverse.expiry(),
+            Some(Expiry {
+                year: 2023,
+                month: 3,
+                day: 31
+            })
+        );
+        assert_eq!(inverse.expiry().unwrap().to_string(), "2023-03-31");
+
+        let linear = parse("FF_XBTUSD_240628");
+        assert_eq!(linear.kind(), ProductKind::LinearFuture);
+        assert_eq!(linear.expiry().map(|expiry| expiry.month), Some(6));
+    }
+
+    #[test]
+    fn longest_quote_wins() {
+        let tether = parse("PF_XBTUSDT");
+        assert_eq!((tether.base(), tether.quote()), (Some("XBT"), Some("USDT")));
+
+        let dollar = parse("PF_XBTUSD");
+        assert_eq!((dollar.base(), dollar.quote()), (Some("XBT"), Some("USD")));
+    }
+
+    #[test]
+    fn symbols_ignore_case() {
+        let private = Product::from("pi_xbtusd");
+
+        assert_eq!(private, Product::from("PI_XBTUSD"));
+        assert_eq!(private.as_str(), "PI_XBTUSD");
+        assert_eq!(parse("fi_xbtusd_230331"), parse("FI_XBTUSD_230331"));
+    }
+
+    #[test]
+    fn rejects_malformed_symbols() {
+        for symbol in [
+            "PI_XBTUSD_230331",
+            "FI_XBTUSD",
+            "FI_XBTUSD_231331",
+            "XX_XBTUSD",
+            "PI_",
+        ] {
+            assert_eq!(
+                symbol.parse::<Product>(),
+                Err(InvalidProduct(symbol.to_owned())),
+                "{symbol}"
+            );
+        }
+
+        // but converting keeps them, so that new instruments still decode
+        let unknown = Product::from("XX_XBTUSD");
+        assert_eq!(unknown.kind(), ProductKind::Unknown);
+        assert!(!unknown.is_well_formed());
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::VecDeque,
+    future::poll_fn,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+use tokio::sync::Notify;
+
+use crate::{Feed, Msg, Product, Result};
+
+/// What the connection task does with incoming messages while the queue read by
+/// [`WebSocket::next_msg`](crate::WebSocket::next_msg) is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backpressure {
+    /// Waits for the consumer to make room.
+    ///
+    /// The socket is not read meanwhile, so a consumer that stalls for long enough can get the
+    /// connection dropped for not answering pings.
+    #[default]
+    Block,
+
+    /// Discards the oldest queued message to make room.
+    DropOldest,
+
+    /// Discards the incoming message.
+    DropNewest,
+
+    /// Discards a queued ticker for the same feed and product as the incoming one, which goes
+    /// to the back of the queue, so only the latest is delivered; other messages wait for room,
+    /// as with [`Backpressure::Block`]. Nothing is conflated while there is room.
+    Conflate,
+}
+
+/// Counts of messages the [`Backpressure`] policy did not deliver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Messages discarded because the queue was full; discards are also reported in-band as
+    /// [`Msg::Dropped`].
+    pub dropped: u64,
+    /// Tickers discarded for a newer one of the same feed and product while the queue was full.
+    pub conflated: u64,
+}
+
+/// The receiving half of the queue has been dropped.
+#[derive(Debug)]
+pub(crate) struct Closed;
+
+struct State {
+    items: VecDeque<Result<Msg>>,
+    stats: QueueStats,
+    // discards not yet reported in-band
+    unreported: u64,
+    waker: Option<Waker>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // signalled whenever the receiver takes an item, or goes away
+    space: Notify,
+    capacity: usize,
+    policy: Backpressure,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl State {
+    fn push(&mut self, item: Result<Msg>) {
+        self.items.push_back(item);
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn drop_one(&mut self) {
+        self.stats.dropped += 1;
+        self.unreported += 1;
+    }
+}
+
+/// Bounded queue of received messages, applying a [`Backpressure`] policy once full.
+pub(crate) fn channel(capacity: usize, policy: Backpressure) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            stats: QueueStats::default(),
+            unreported: 0,
+            waker: None,
+            sender_closed: false,
+            receiver_closed: false,
+        }),
+        space: Notify::new(),
+        capacity: capacity.max(1),
+        policy,
+    });
+
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+// feed and product of messages that only matter until a newer one arrives
+fn conflation_key(item: &Result<Msg>) -> Option<(Feed, &Product)> {
+    match item {
+        Ok(Msg::Ticker(ticker)) => Some((Feed::Ticker, &ticker.ticker_lite.product_id)),
+        Ok(Msg::TickerLite(ticker)) => Some((Feed::TickerLite, &ticker.product_id)),
+        _ => None,
+    }
+}
+
+pub(crate) struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    /// Queues `item`, applying the backpressure policy if the queue is full.
+    pub(crate) async fn send(&self, item: Result<Msg>) -> Result<(), Closed> {
+        loop {
+            {
+                let mut state = self.shared.lock();
+
+                if state.receiver_closed {
+                    return Err(Closed);
+                }
+
+                if state.items.len() < self.shared.capacity {
+                    state.push(item);
+                    return Ok(());
+                }
+
+                match self.shared.policy {
+                    Backpressure::DropOldest => {
+                        state.items.pop_front();
+                        state.drop_one();
+                        state.push(item);
+                        return Ok(());
+                    }
+                    Backpressure::DropNewest => {
+                        state.drop_one();
+                        return Ok(());
+                    }
+                    Backpressure::Conflate => {
+                        let queued = conflation_key(&item).and_then(|key| {
+                            state
+                                .items
+                                .iter()
+                                .position(|queued| conflation_key(queued) == Some(key))
+                        });
+
+                        if let Some(index) = queued {
+                            state.items.remove(index);
+                            state.stats.conflated += 1;
+                            state.push(item);
+                            return Ok(());
+                        }
+                    }
+                    Backpressure::Block => {}
+                }
+            }
+
+            self.shared.space.notified().await;
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared.lock().receiver_closed
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.sender_closed = true;
+
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    pub(crate) async fn recv(&mut self) -> Option<Result<Msg>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Takes the next item, reporting any discards that happened since the last one first.
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Msg>>> {
+        let mut state = self.shared.lock();
+
+        if state.unreported > 0 {
+            let dropped = std::mem::take(&mut state.unreported);
+            return Poll::Ready(Some(Ok(Msg::Dropped(dropped))));
+        }
+
+        if let Some(item) = state.items.pop_front() {
+            drop(state);
+            self.shared.space.notify_one();
+            return Poll::Ready(Some(item));
+        }
+
+        if state.sender_closed {
+            return Poll::Ready(None);
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        self.shared.lock().stats
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_closed = true;
+        state.items.clear();
+        drop(state);
+
+        // a blocked sender must notice that nobody is listening any more
+        self.shared.space.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn msg(i: u64) -> Result<Msg> {
+        Ok(Msg::Unknown(json!(i)))
+    }
+
+    fn ticker(product: &str, bid: u32) -> Result<Msg> {
+        let ticker = json!({
+            "feed": "ticker_lite", "product_id": product, "bid": bid, "ask": bid + 1,
+            "change": 0, "premium": 0, "volume": 1, "tag": "perpetual", "pair": "XBT:USD",
+            "dtm": 0, "maturityTime": 0,
+        });
+        Ok(ticker.to_string().parse().unwrap())
+    }
+
+    // identifies the items of `msg` and `ticker`
+    async fn next(rx: &mut QueueReceiver) -> String {
+        match rx.recv().await {
+            Some(Ok(Msg::Unknown(value))) => value.to_string(),
+            Some(Ok(Msg::TickerLite(ticker))) => format!("{}@{}", ticker.product_id, ticker.bid),
+            Some(Ok(Msg::Dropped(dropped))) => format!("dropped {dropped}"),
+            item => panic!("unexpected item: {item:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (tx, mut rx) = channel(1, Backpressure::Block);
+        tx.send(msg(1)).await.unwrap();
+
+        let mut send = Box::pin(tx.send(msg(2)));
+        assert!(timeout(Duration::from_millis(10), &mut send).await.is_err());
+
+        assert_eq!(next(&mut rx).await, "1");
+        send.await.unwrap();
+        assert_eq!(next(&mut rx).await, "2");
+        assert_eq!(rx.stats(), QueueStats::default());
+    }
+
+    #[tokio::test]
+    async fn blocked_sender_sees_dropped_receiver() {
+        let (tx, rx) = channel(1, Backpressure::Block);
+        tx.send(msg(1)).await.unwrap();
+
+        let send = tokio::spawn(async move { tx.send(msg(2)).await });
+        tokio::task::yield_now().await;
+        drop(rx);
+
+        assert!(send.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (tx, mut rx) = channel(2, Backpressure::DropOldest);
+        for i in 1..=5 {
+            tx.send(msg(i)).await.unwrap();
+        }
+
+        assert_eq!(rx.stats().dropped, 3);
+        assert_eq!(next(&mut rx).await, "dropped 3");
+        assert_eq!(next(&mut rx).await, "4");
+        assert_eq!(next(&mut rx).await, "5");
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let (tx, mut rx) = channel(2, Backpressure::DropNewest);
+        for i in 1..=5 {
+            tx.send(msg(i)).await.unwrap();
+        }
+
+        assert_eq!(rx.stats().dropped, 3);
+        assert_eq!(next(&mut rx).await, "dropped 3");
+        assert_eq!(next(&mut rx).await, "1");
+        assert_eq!(next(&mut rx).await, "2");
+    }
+
+    #[tokio::test]
+    async fn drops_are_reported_before_the_next_item() {
+        let (tx, mut rx) = channel(1, Backpressure::DropNewest);
+        tx.send(msg(1)).await.unwrap();
+        tx.send(msg(2)).await.unwrap();
+        assert_eq!(next(&mut rx).await, "dropped 1");
+        assert_eq!(next(&mut rx).await, "1");
+
+        tx.send(msg(3)).await.unwrap();
+        tx.send(msg(4)).await.unwrap();
+        tx.send(msg(5)).await.unwrap();
+        assert_eq!(next(&mut rx).await, "dropped 2");
+        assert_eq!(next(&mut rx).await, "3");
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+        assert_eq!(rx.stats().dropped, 3);
+    }
+
+    #[tokio::test]
+    async fn conflates_tickers_only_when_full() {
+        let (tx, mut rx) = channel(3, Backpressure::Conflate);
+        tx.send(ticker("PI_XBTUSD", 100)).await.unwrap();
+        tx.send(ticker("PI_XBTUSD", 101)).await.unwrap();
+        tx.send(ticker("PI_ETHUSD", 10)).await.unwrap();
+        assert_eq!(rx.stats().conflated, 0);
+
+        tx.send(ticker("PI_XBTUSD", 102)).await.unwrap();
+        assert_eq!(rx.stats().conflated, 1);
+
+        assert_eq!(next(&mut rx).await, "PI_XBTUSD@101");
+        assert_eq!(next(&mut rx).await, "PI_ETHUSD@10");
+        assert_eq!(next(&mut rx).await, "PI_XBTUSD@102");
+    }
+
+    #[tokio::test]
+    async fn conflate_blocks_other_messages() {
+        let (tx, mut rx) = channel(1, Backpressure::Conflate);
+        tx.send(ticker("PI_XBTUSD", 100)).await.unwrap();
+
+        let mut send = Box::pin(tx.send(msg(1)));
+        assert!(timeout(Duration::from_millis(10), &mut send).await.is_err());
+
+        assert_eq!(next(&mut rx).await, "PI_XBTUSD@100");
+        send.await.unwrap();
+        assert_eq!(next(&mut rx).await, "1");
+        assert_eq!(rx.stats(), QueueStats::default());
+    }
+}
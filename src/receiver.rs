@@ -0,0 +1,38 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{queue::QueueReceiver, Error, Msg, QueueStats, Result};
+use futures_util::Stream;
+
+/// Reading half of a connection; see [`WebSocket::split`](crate::WebSocket::split).
+///
+/// It is also a [`Stream`] of the same items as [`Receiver::next_msg`], which ends once the
+/// connection has been shut down.
+pub struct Receiver {
+    pub(crate) rx: QueueReceiver,
+}
+
+impl Receiver {
+    /// Waits for the next message from the server.
+    ///
+    /// Returns [`Error::ChannelClosed`] once the connection has been shut down.
+    pub async fn next_msg(&mut self) -> Result<Msg> {
+        self.rx.recv().await.unwrap_or(Err(Error::ChannelClosed))
+    }
+
+    /// Returns how many messages the [`Backpressure`](crate::Backpressure) policy has discarded
+    /// or conflated.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.rx.stats()
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Result<Msg>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
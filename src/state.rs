@@ -0,0 +1,32 @@
+/// Lifecycle state of the connection to the exchange.
+///
+/// The current state is available through [`WebSocket::state`](crate::WebSocket::state) and every
+/// transition is also delivered in-band as [`Msg::ConnectionState`](crate::Msg::ConnectionState).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionState {
+    /// The initial connection is being established.
+    Connecting,
+
+    /// The socket is open; public feeds can be used.
+    Connected,
+
+    /// The API challenge has been signed; private feeds can be used.
+    Authenticated,
+
+    /// The socket was lost. `close_code` is set when the server sent a close frame.
+    Disconnected {
+        reason: String,
+        close_code: Option<u16>,
+    },
+
+    /// A new connection is about to be attempted.
+    Reconnecting { attempt: u32 },
+}
+
+impl ConnectionState {
+    /// Returns true if the socket is currently usable.
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Connected | Self::Authenticated)
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{ready, Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::{
+    AccountBalancesAndMargins, BookInvalidated, BookSnapshot, BookValue, Fill, Msg, Notification,
+    OpenPositions, Product, Ticker, Trade,
+};
+
+/// Change to the order book of one product, as delivered by [`WebSocket::book_updates`].
+///
+/// [`WebSocket::book_updates`]: crate::WebSocket::book_updates
+#[derive(Debug)]
+pub enum BookUpdate {
+    Snapshot(BookSnapshot),
+    Delta(BookValue),
+    Invalidated(BookInvalidated),
+}
+
+/// Stream of one kind of message, routed to it by the connection task.
+///
+/// Every stream a message matches gets a copy of it, so several streams of the same product
+/// each see all of its messages. Messages routed to a stream are not delivered by
+/// [`WebSocket::next_msg`](crate::WebSocket::next_msg); once every stream they match is dropped,
+/// they go back to `next_msg`.
+///
+/// The connection task never waits for a stream: a message arriving while the stream's buffer
+/// is full is discarded and counted by [`FeedStream::dropped`]. The stream ends once the
+/// connection has been shut down.
+pub struct FeedStream<T> {
+    rx: mpsc::Receiver<Msg>,
+    extract: fn(Msg, &mut VecDeque<T>),
+    // items of a snapshot that have not been yielded yet
+    pending: VecDeque<T>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> FeedStream<T> {
+    /// Returns how many messages were discarded because the stream was not read fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+// `T` is never pinned
+impl<T> Unpin for FeedStream<T> {}
+
+impl<T> Stream for FeedStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = &mut *self;
+
+        loop {
+            if let Some(item) = this.pending.pop_front() {
+                return Poll::Ready(Some(item));
+            }
+
+            match ready!(this.rx.poll_recv(cx)) {
+                Some(msg) => (this.extract)(msg, &mut this.pending),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+struct Route {
+    product: Option<Product>,
+    accepts: fn(&Msg, Option<&Product>) -> bool,
+    tx: mpsc::Sender<Msg>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Route {
+    fn deliver(&self, msg: Msg) {
+        // a closed stream is pruned on the next dispatch
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(msg) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            log::warn!("feed stream is full; {dropped} messages dropped so far");
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    routes: Vec<Route>,
+    // set once the connection task is done; streams added afterwards end at once
+    closed: bool,
+}
+
+/// Streams registered by the client, shared with the connection task.
+#[derive(Clone)]
+pub(crate) struct Routes {
+    inner: Arc<Mutex<Inner>>,
+    capacity: usize,
+}
+
+impl Routes {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity,
+        }
+    }
+
+    fn add<T>(
+        &self,
+        product: Option<Product>,
+        accepts: fn(&Msg, Option<&Product>) -> bool,
+        extract: fn(Msg, &mut VecDeque<T>),
+    ) -> FeedStream<T> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let dropped = Arc::default();
+
+        let mut inner = self.lock();
+
+        if !inner.closed {
+            inner.routes.push(Route {
+                product,
+                accepts,
+                tx,
+                dropped: Arc::clone(&dropped),
+            });
+        }
+
+        FeedStream {
+            rx,
+            extract,
+            pending: VecDeque::new(),
+            dropped,
+        }
+    }
+
+    // Passes a copy of `msg` on to every stream it is routed to. Returns it back if it is not
+    // routed, or all of its streams have been dropped.
+    pub(crate) fn dispatch(&self, msg: Msg) -> Option<Msg> {
+        let mut inner = self.lock();
+        inner.routes.retain(|route| !route.tx.is_closed());
+
+        let routes: Vec<&Route> = inner
+            .routes
+            .iter()
+            .filter(|route| (route.accepts)(&msg, route.product.as_ref()))
+            .collect();
+
+        let Some((last, others)) = routes.split_last() else {
+            return Some(msg);
+        };
+
+        for route in others {
+            route.deliver(msg.clone());
+        }
+        last.deliver(msg);
+
+        None
+    }
+
+    // Ends every stream, current and future; called when the connection task is done.
+    pub(crate) fn close(&self) {
+        let mut inner = self.lock();
+        inner.closed = true;
+        inner.routes.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn trades(&self, product: Product) -> FeedStream<Trade> {
+        self.add(
+            Some(product),
+            |msg, product| match msg {
+                Msg::Trade(trade) => trade.product_id.as_ref() == product,
+                Msg::TradeSnapshot(snapshot) => snapshot
+                    .trades
+                    .first()
+                    .is_some_and(|trade| trade.product_id.as_ref() == product),
+                _ => false,
+            },
+            |msg, items| match msg {
+                Msg::Trade(trade) => items.push_back(trade),
+                Msg::TradeSnapshot(snapshot) => items.extend(snapshot.trades),
+                _ => {}
+            },
+        )
+    }
+
+    pub(crate) fn tickers(&self, product: Product) -> FeedStream<Ticker> {
+        self.add(
+            Some(product),
+            |msg, product| {
+                matches!(msg, Msg::Ticker(ticker) if Some(&ticker.ticker_lite.product_id) == product)
+            },
+            |msg, items| {
+                if let Msg::Ticker(ticker) = msg {
+                    items.push_back(ticker);
+                }
+            },
+        )
+    }
+
+    pub(crate) fn book_updates(&self, product: Product) -> FeedStream<BookUpdate> {
+        self.add(
+            Some(product),
+            |msg, product| match msg {
+                Msg::BookSnapshot(snapshot) => Some(&snapshot.product_id) == product,
+                Msg::Book(delta) => delta.product_id.as_ref() == product,
+                Msg::BookInvalidated(invalidated) => Some(&invalidated.product) == product,
+                _ => false,
+            },
+            |msg, items| match msg {
+                Msg::BookSnapshot(snapshot) => items.push_back(BookUpdate::Snapshot(snapshot)),
+                Msg::Book(delta) => items.push_back(BookUpdate::Delta(delta)),
+                Msg::BookInvalidated(invalidated) => {
+                    items.push_back(BookUpdate::Invalidated(invalidated))
+                }
+                _ => {}
+            },
+        )
+    }
+
+    pub(crate) fn fills(&self) -> FeedStream<Fill> {
+        self.add(
+            None,
+            |msg, _| matches!(msg, Msg::Fills(_) | Msg::FillsSnapshot(_)),
+            |msg, items| match msg {
+                Msg::Fills(fills) => items.extend(fills.fills),
+                Msg::FillsSnapshot(snapshot) => items.extend(snapshot.fills),
+                _ => {}
+            },
+        )
+    }
+
+    pub(crate) fn positions(&self) -> FeedStream<OpenPositions> {
+        self.add(
+            None,
+            |msg, _| matches!(msg, Msg::OpenPositions(_)),
+            |msg, items| {
+                if let Msg::OpenPositions(positions) = msg {
+                    items.push_back(positions);
+                }
+            },
+        )
+    }
+
+    pub(crate) fn balances(&self) -> FeedStream<AccountBalancesAndMargins> {
+        self.add(
+            None,
+            |msg, _| matches!(msg, Msg::AccountBalancesAndMargins(_)),
+            |msg, items| {
+                if let Msg::AccountBalancesAndMargins(balances) = msg {
+                    items.push_back(balances);
+                }
+            },
+        )
+    }
+
+    pub(crate) fn notifications(&self) -> FeedStream<Notification> {
+        self.add(
+            None,
+            |msg, _| matches!(msg, Msg::Notifications(_)),
+            |msg, items| {
+                if let Msg::Notifications(notifications) = msg {
+                    items.extend(notifications.notifications);
+                }
+            },
+        )
+    }
+}
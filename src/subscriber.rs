@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{Error, Msg, Result};
+
+/// What a [`Subscriber`] does when it falls so far behind that messages are overwritten before
+/// it receives them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Lag {
+    /// Skips the missed messages and carries on with the oldest one still queued.
+    Skip,
+
+    /// Returns [`Error::Lagged`] once, then carries on with the oldest message still queued.
+    #[default]
+    Report,
+
+    /// Returns [`Error::Lagged`] once, then [`Error::ChannelClosed`] on every later call.
+    Close,
+}
+
+/// Handle that receives every incoming message, shared with any other subscribers.
+///
+/// Subscribers only receive messages on a connection built with
+/// [`WebSocketBuilder::broadcast`](crate::WebSocketBuilder::broadcast); otherwise they are closed
+/// from the start. In that mode messages go to subscribers instead of
+/// [`WebSocket::next_msg`](crate::WebSocket::next_msg), except those taken by a
+/// [`FeedStream`](crate::FeedStream), whether or not any subscriber exists. Failures are
+/// delivered as [`Msg::Failure`]; `next_msg` receives nothing, and the
+/// [`Receiver`](crate::Receiver) can be dropped without closing the connection.
+///
+/// Messages are delivered as [`Arc<Msg>`], so each one is decoded once however many subscribers
+/// there are. Subscribers never slow the connection down: one that falls more than the broadcast
+/// capacity behind misses messages and reacts as set by its [`Lag`] policy.
+#[derive(Debug)]
+pub struct Subscriber {
+    rx: broadcast::Receiver<Arc<Msg>>,
+    lag: Lag,
+    closed: bool,
+}
+
+impl Subscriber {
+    pub(crate) fn new(rx: broadcast::Receiver<Arc<Msg>>, lag: Lag) -> Self {
+        Self {
+            rx,
+            lag,
+            closed: false,
+        }
+    }
+
+    /// Waits for the next message.
+    ///
+    /// Returns [`Error::ChannelClosed`] once the connection has been shut down.
+    pub async fn recv(&mut self) -> Result<Arc<Msg>> {
+        loop {
+            if self.closed {
+                return Err(Error::ChannelClosed);
+            }
+
+            match self.rx.recv().await {
+                Ok(msg) => return Ok(msg),
+                Err(RecvError::Closed) => self.closed = true,
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("subscriber missed {missed} messages");
+
+                    match self.lag {
+                        Lag::Skip => {}
+                        Lag::Report => return Err(Error::Lagged(missed)),
+                        Lag::Close => {
+                            self.closed = true;
+                            return Err(Error::Lagged(missed));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the lag policy of this subscriber.
+    pub fn lag(&self) -> Lag {
+        self.lag
+    }
+
+    /// Changes the lag policy of this subscriber.
+    pub fn set_lag(&mut self, lag: Lag) {
+        self.lag = lag;
+    }
+
+    /// Returns a new subscriber with the same lag policy, which receives messages from now on.
+    pub fn resubscribe(&self) -> Self {
+        Self::new(self.rx.resubscribe(), self.lag)
+    }
+}
+
+/// Source of new subscribers, shared by the client handles.
+///
+/// Only the connection task holds the sending half, so subscribers see the channel close once
+/// the task is done.
+#[derive(Debug)]
+pub(crate) struct Subscribers {
+    // `None` unless broadcast mode is enabled
+    rx: Option<broadcast::Receiver<Arc<Msg>>>,
+    lag: Lag,
+}
+
+impl Subscribers {
+    pub(crate) fn new(rx: Option<broadcast::Receiver<Arc<Msg>>>, lag: Lag) -> Self {
+        Self { rx, lag }
+    }
+
+    pub(crate) fn subscribe(&self) -> Subscriber {
+        let rx = match &self.rx {
+            Some(rx) => rx.resubscribe(),
+            // the sender is dropped at once, so the subscriber is closed
+            None => broadcast::channel(1).1,
+        };
+
+        Subscriber::new(rx, self.lag)
+    }
+}
+
+impl Clone for Subscribers {
+    fn clone(&self) -> Self {
+        Self::new(
+            self.rx.as_ref().map(broadcast::Receiver::resubscribe),
+            self.lag,
+        )
+    }
+}
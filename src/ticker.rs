@@ -0,0 +1,93 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::watch;
+
+use crate::{models, Product, Ticker, TickerLite};
+
+/// Newest tickers of one product.
+#[derive(Debug, Clone, Default)]
+pub struct LatestTicker {
+    /// Latest message of the `ticker` feed.
+    pub ticker: Option<Ticker>,
+    /// Latest message of the `ticker_lite` feed.
+    pub ticker_lite: Option<TickerLite>,
+}
+
+impl LatestTicker {
+    /// Returns true until a ticker of either feed has been received.
+    pub fn is_empty(&self) -> bool {
+        self.ticker.is_none() && self.ticker_lite.is_none()
+    }
+}
+
+/// Newest tickers of every product received on the `ticker` and `ticker_lite` feeds, kept by
+/// the connection task.
+///
+/// Tickers are kept across reconnects, so they can be stale while the connection is down.
+/// Cloning the handle is cheap; all clones share the same cache.
+#[derive(Debug, Clone, Default)]
+pub struct TickerCache {
+    inner: Arc<Mutex<BTreeMap<Product, watch::Sender<LatestTicker>>>>,
+}
+
+impl TickerCache {
+    /// Returns the newest tickers of `product`, if any has been received.
+    pub fn get(&self, product: &Product) -> Option<LatestTicker> {
+        self.lock()
+            .get(product)
+            .map(|tx| tx.borrow().clone())
+            .filter(|latest| !latest.is_empty())
+    }
+
+    /// Returns a receiver that is notified of every new ticker of `product`.
+    ///
+    /// It can be created before the first ticker arrives, in which case it starts out empty.
+    pub fn watch(&self, product: impl Into<Product>) -> watch::Receiver<LatestTicker> {
+        self.lock()
+            .entry(product.into())
+            .or_insert_with(|| watch::channel(LatestTicker::default()).0)
+            .subscribe()
+    }
+
+    /// Returns the newest tickers of every product.
+    pub fn snapshot(&self) -> BTreeMap<Product, LatestTicker> {
+        self.lock()
+            .iter()
+            .map(|(product, tx)| (product.clone(), tx.borrow().clone()))
+            .filter(|(_, latest)| !latest.is_empty())
+            .collect()
+    }
+
+    // keeps tickers as the newest of their product
+    pub(crate) fn update(&self, msg: &models::Msg) {
+        match msg {
+            models::Msg::Ticker(ticker) => self.modify(&ticker.ticker_lite.product_id, |latest| {
+                latest.ticker = Some(ticker.clone());
+            }),
+            models::Msg::TickerLite(ticker) => self.modify(&ticker.product_id, |latest| {
+                latest.ticker_lite = Some(ticker.clone());
+            }),
+            _ => {}
+        }
+    }
+
+    fn modify(&self, product: &Product, f: impl FnOnce(&mut LatestTicker)) {
+        let mut inner = self.lock();
+
+        match inner.get(product) {
+            Some(tx) => tx.send_modify(f),
+            None => {
+                let mut latest = LatestTicker::default();
+                f(&mut latest);
+                inner.insert(product.clone(), watch::channel(latest).0);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<Product, watch::Sender<LatestTicker>>> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}